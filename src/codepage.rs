@@ -0,0 +1,406 @@
+// UnQuantum - DOS code page decoding for file names and comments
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Quantum archives store names and comments as raw bytes in whatever code
+// page the DOS machine that created them used. The single-byte tables below
+// cover the 0x80-0xFF half of each page; 0x00-0x7F is plain ASCII.
+
+mod sjis;
+
+/// A code page used to decode archived names and comments
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CodePage {
+    /// US (the DOS default)
+    Cp437,
+    /// Multilingual Latin 1 (Western Europe)
+    Cp850,
+    /// Latin 2 (Central Europe)
+    Cp852,
+    /// Cyrillic (Russian)
+    Cp866,
+    /// Japanese (code page 932)
+    ShiftJis,
+    /// Guess from the archive's names and comments
+    Auto,
+}
+
+/// Code pages considered by auto-detection, in tie-break order
+const CANDIDATES: [CodePage; 5] = [
+    CodePage::Cp437,
+    CodePage::Cp850,
+    CodePage::Cp852,
+    CodePage::Cp866,
+    CodePage::ShiftJis,
+];
+
+impl CodePage {
+    /// Parse a --codepage value
+    pub fn parse(name: &str) -> Result<CodePage, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "437" | "cp437" | "ibm437" => Ok(CodePage::Cp437),
+            "850" | "cp850" | "ibm850" => Ok(CodePage::Cp850),
+            "852" | "cp852" | "ibm852" => Ok(CodePage::Cp852),
+            "866" | "cp866" | "ibm866" => Ok(CodePage::Cp866),
+            "932" | "cp932" | "sjis" | "shift-jis" | "shift_jis" => {
+                Ok(CodePage::ShiftJis)
+            }
+            "auto" => Ok(CodePage::Auto),
+            _ => Err(format!(
+                "Unknown code page '{}' (known: cp437, cp850, cp852, cp866, \
+                 shift-jis, auto)",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CodePage::Cp437 => "cp437",
+            CodePage::Cp850 => "cp850",
+            CodePage::Cp852 => "cp852",
+            CodePage::Cp866 => "cp866",
+            CodePage::ShiftJis => "shift-jis",
+            CodePage::Auto => "auto",
+        }
+    }
+
+    /// Turn `Auto` into a concrete code page by looking at sample strings.
+    /// Concrete code pages are returned unchanged.
+    pub fn resolve(self, samples: &[&[u8]]) -> CodePage {
+        if self != CodePage::Auto {
+            return self;
+        }
+        if samples.iter().all(|s| s.is_ascii()) {
+            return CodePage::Cp437;
+        }
+        let mut best = CodePage::Cp437;
+        let mut best_score = i64::MIN;
+        for cp in CANDIDATES {
+            let score = samples.iter().map(|s| cp.score(s)).sum::<Option<i64>>();
+            if let Some(score) = score {
+                if score > best_score {
+                    best = cp;
+                    best_score = score;
+                }
+            }
+        }
+        best
+    }
+
+    /// Decode raw bytes. `Auto` decodes the string on its own merits.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let table = match self {
+            CodePage::Cp437 => &CP437,
+            CodePage::Cp850 => &CP850,
+            CodePage::Cp852 => &CP852,
+            CodePage::Cp866 => &CP866,
+            CodePage::ShiftJis => return decode_sjis(bytes),
+            CodePage::Auto => return CodePage::Auto.resolve(&[bytes]).decode(bytes),
+        };
+        bytes
+            .iter()
+            .map(|&b| if b < 0x80 { b as char } else { table[b as usize - 0x80] })
+            .collect()
+    }
+
+    /// Plausibility score of a string in this code page, or None if the
+    /// bytes are not valid in it. Accented Latin letters and runs of
+    /// Cyrillic score points; symbols, which rarely appear in names, cost
+    /// points, and so do letters that break the case of their word (`éÆ`),
+    /// as text read in the wrong code page does. Line drawing is neutral, as
+    /// DOS comments often use it.
+    fn score(&self, bytes: &[u8]) -> Option<i64> {
+        if *self == CodePage::ShiftJis {
+            return score_sjis(bytes);
+        }
+        let chars: Vec<char> = self.decode(bytes).chars().collect();
+        let cyrillic = |i: usize| {
+            chars
+                .get(i)
+                .is_some_and(|c| ('\u{0400}'..='\u{04FF}').contains(c))
+        };
+        let mut score = 0i64;
+        for (i, &c) in chars.iter().enumerate() {
+            if case_break(&chars, i) {
+                score -= 2;
+            }
+            if c.is_ascii() {
+                continue;
+            }
+            let prev = i.checked_sub(1);
+            score += match c {
+                '\u{00C0}'..='\u{017F}' if c.is_alphabetic() => 2,
+                '\u{0400}'..='\u{04FF}' => {
+                    if cyrillic(i + 1) || prev.is_some_and(cyrillic) {
+                        2
+                    } else {
+                        0
+                    }
+                }
+                '\u{2500}'..='\u{259F}' => 0,
+                _ if c.is_alphabetic() => 0,
+                _ => -1,
+            };
+        }
+        Some(score)
+    }
+}
+
+/// Whether `chars[i]` and the letter before it, one of them not ASCII,
+/// differ in case other than at the capital of a word: `Éc` and `ÄÜ` are
+/// fine, `éC` and `ÉÉc` are not
+fn case_break(chars: &[char], i: usize) -> bool {
+    let Some(prev) = i.checked_sub(1).map(|p| chars[p]) else {
+        return false;
+    };
+    let c = chars[i];
+    if !prev.is_alphabetic()
+        || !c.is_alphabetic()
+        || (prev.is_ascii() && c.is_ascii())
+        || prev.is_uppercase() == c.is_uppercase()
+    {
+        return false;
+    }
+    let capital = prev.is_uppercase()
+        && (i < 2 || !chars[i - 2].is_alphabetic());
+    !capital
+}
+
+/// Plausibility score of a string as Shift-JIS, or None if it is invalid.
+/// Double-byte characters whose trail byte is also high are typical of
+/// Japanese text, and outweigh the two letters the same bytes make in a
+/// single-byte code page; trail bytes in the ASCII range are weaker
+/// evidence, since they also occur when Western text is misread. Half-width
+/// katakana share their byte values with line drawing, so they count for
+/// nothing.
+fn score_sjis(bytes: &[u8]) -> Option<i64> {
+    let mut score = 0i64;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0x00..=0x7F => i += 1,
+            0xA1..=0xDF => i += 1,
+            _ => {
+                let pair = bytes.get(i..i + 2)?;
+                if decode_sjis(pair).contains('\u{FFFD}') {
+                    return None;
+                }
+                score += if pair[1] >= 0x80 { 4 } else { 2 };
+                i += 2;
+            }
+        }
+    }
+    Some(score)
+}
+
+/// Decode Shift-JIS: ASCII, half-width katakana and double-byte JIS X 0208
+/// (with the CP932 extensions). Invalid sequences become U+FFFD.
+fn decode_sjis(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        match b {
+            0x00..=0x7F => out.push(b as char),
+            0xA1..=0xDF => {
+                out.push(char::from_u32(0xFF61 + (b as u32 - 0xA1)).unwrap())
+            }
+            0x81..=0x9F | 0xE0..=0xFC => {
+                let row = if b <= 0x9F { b - 0x81 } else { b - 0xE0 + 31 };
+                let decoded = bytes
+                    .get(i)
+                    .filter(|&&t| (0x40..=0xFC).contains(&t) && t != 0x7F)
+                    .map(|&t| {
+                        sjis::SJIS_DOUBLE[row as usize * 189 + (t - 0x40) as usize]
+                    })
+                    .filter(|&u| u != 0)
+                    .and_then(|u| char::from_u32(u as u32));
+                match decoded {
+                    Some(c) => {
+                        out.push(c);
+                        i += 1;
+                    }
+                    None => out.push('\u{FFFD}'),
+                }
+            }
+            _ => out.push('\u{FFFD}'),
+        }
+    }
+    out
+}
+
+/// Code page 437, 0x80-0xFF
+const CP437: [char; 128] = [
+    '\u{00C7}', '\u{00FC}', '\u{00E9}', '\u{00E2}', '\u{00E4}', '\u{00E0}',
+    '\u{00E5}', '\u{00E7}', '\u{00EA}', '\u{00EB}', '\u{00E8}', '\u{00EF}',
+    '\u{00EE}', '\u{00EC}', '\u{00C4}', '\u{00C5}', '\u{00C9}', '\u{00E6}',
+    '\u{00C6}', '\u{00F4}', '\u{00F6}', '\u{00F2}', '\u{00FB}', '\u{00F9}',
+    '\u{00FF}', '\u{00D6}', '\u{00DC}', '\u{00A2}', '\u{00A3}', '\u{00A5}',
+    '\u{20A7}', '\u{0192}', '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}',
+    '\u{00F1}', '\u{00D1}', '\u{00AA}', '\u{00BA}', '\u{00BF}', '\u{2310}',
+    '\u{00AC}', '\u{00BD}', '\u{00BC}', '\u{00A1}', '\u{00AB}', '\u{00BB}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{2561}',
+    '\u{2562}', '\u{2556}', '\u{2555}', '\u{2563}', '\u{2551}', '\u{2557}',
+    '\u{255D}', '\u{255C}', '\u{255B}', '\u{2510}', '\u{2514}', '\u{2534}',
+    '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{255E}', '\u{255F}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}',
+    '\u{256C}', '\u{2567}', '\u{2568}', '\u{2564}', '\u{2565}', '\u{2559}',
+    '\u{2558}', '\u{2552}', '\u{2553}', '\u{256B}', '\u{256A}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{258C}', '\u{2590}', '\u{2580}',
+    '\u{03B1}', '\u{00DF}', '\u{0393}', '\u{03C0}', '\u{03A3}', '\u{03C3}',
+    '\u{00B5}', '\u{03C4}', '\u{03A6}', '\u{0398}', '\u{03A9}', '\u{03B4}',
+    '\u{221E}', '\u{03C6}', '\u{03B5}', '\u{2229}', '\u{2261}', '\u{00B1}',
+    '\u{2265}', '\u{2264}', '\u{2320}', '\u{2321}', '\u{00F7}', '\u{2248}',
+    '\u{00B0}', '\u{2219}', '\u{00B7}', '\u{221A}', '\u{207F}', '\u{00B2}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+/// Code page 850, 0x80-0xFF
+const CP850: [char; 128] = [
+    '\u{00C7}', '\u{00FC}', '\u{00E9}', '\u{00E2}', '\u{00E4}', '\u{00E0}',
+    '\u{00E5}', '\u{00E7}', '\u{00EA}', '\u{00EB}', '\u{00E8}', '\u{00EF}',
+    '\u{00EE}', '\u{00EC}', '\u{00C4}', '\u{00C5}', '\u{00C9}', '\u{00E6}',
+    '\u{00C6}', '\u{00F4}', '\u{00F6}', '\u{00F2}', '\u{00FB}', '\u{00F9}',
+    '\u{00FF}', '\u{00D6}', '\u{00DC}', '\u{00F8}', '\u{00A3}', '\u{00D8}',
+    '\u{00D7}', '\u{0192}', '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}',
+    '\u{00F1}', '\u{00D1}', '\u{00AA}', '\u{00BA}', '\u{00BF}', '\u{00AE}',
+    '\u{00AC}', '\u{00BD}', '\u{00BC}', '\u{00A1}', '\u{00AB}', '\u{00BB}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{00C1}',
+    '\u{00C2}', '\u{00C0}', '\u{00A9}', '\u{2563}', '\u{2551}', '\u{2557}',
+    '\u{255D}', '\u{00A2}', '\u{00A5}', '\u{2510}', '\u{2514}', '\u{2534}',
+    '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{00E3}', '\u{00C3}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}',
+    '\u{256C}', '\u{00A4}', '\u{00F0}', '\u{00D0}', '\u{00CA}', '\u{00CB}',
+    '\u{00C8}', '\u{0131}', '\u{00CD}', '\u{00CE}', '\u{00CF}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{00A6}', '\u{00CC}', '\u{2580}',
+    '\u{00D3}', '\u{00DF}', '\u{00D4}', '\u{00D2}', '\u{00F5}', '\u{00D5}',
+    '\u{00B5}', '\u{00FE}', '\u{00DE}', '\u{00DA}', '\u{00DB}', '\u{00D9}',
+    '\u{00FD}', '\u{00DD}', '\u{00AF}', '\u{00B4}', '\u{00AD}', '\u{00B1}',
+    '\u{2017}', '\u{00BE}', '\u{00B6}', '\u{00A7}', '\u{00F7}', '\u{00B8}',
+    '\u{00B0}', '\u{00A8}', '\u{00B7}', '\u{00B9}', '\u{00B3}', '\u{00B2}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+/// Code page 852, 0x80-0xFF
+const CP852: [char; 128] = [
+    '\u{00C7}', '\u{00FC}', '\u{00E9}', '\u{00E2}', '\u{00E4}', '\u{016F}',
+    '\u{0107}', '\u{00E7}', '\u{0142}', '\u{00EB}', '\u{0150}', '\u{0151}',
+    '\u{00EE}', '\u{0179}', '\u{00C4}', '\u{0106}', '\u{00C9}', '\u{0139}',
+    '\u{013A}', '\u{00F4}', '\u{00F6}', '\u{013D}', '\u{013E}', '\u{015A}',
+    '\u{015B}', '\u{00D6}', '\u{00DC}', '\u{0164}', '\u{0165}', '\u{0141}',
+    '\u{00D7}', '\u{010D}', '\u{00E1}', '\u{00ED}', '\u{00F3}', '\u{00FA}',
+    '\u{0104}', '\u{0105}', '\u{017D}', '\u{017E}', '\u{0118}', '\u{0119}',
+    '\u{00AC}', '\u{017A}', '\u{010C}', '\u{015F}', '\u{00AB}', '\u{00BB}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{00C1}',
+    '\u{00C2}', '\u{011A}', '\u{015E}', '\u{2563}', '\u{2551}', '\u{2557}',
+    '\u{255D}', '\u{017B}', '\u{017C}', '\u{2510}', '\u{2514}', '\u{2534}',
+    '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{0102}', '\u{0103}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}',
+    '\u{256C}', '\u{00A4}', '\u{0111}', '\u{0110}', '\u{010E}', '\u{00CB}',
+    '\u{010F}', '\u{0147}', '\u{00CD}', '\u{00CE}', '\u{011B}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{0162}', '\u{016E}', '\u{2580}',
+    '\u{00D3}', '\u{00DF}', '\u{00D4}', '\u{0143}', '\u{0144}', '\u{0148}',
+    '\u{0160}', '\u{0161}', '\u{0154}', '\u{00DA}', '\u{0155}', '\u{0170}',
+    '\u{00FD}', '\u{00DD}', '\u{0163}', '\u{00B4}', '\u{00AD}', '\u{02DD}',
+    '\u{02DB}', '\u{02C7}', '\u{02D8}', '\u{00A7}', '\u{00F7}', '\u{00B8}',
+    '\u{00B0}', '\u{00A8}', '\u{02D9}', '\u{0171}', '\u{0158}', '\u{0159}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+/// Code page 866, 0x80-0xFF
+const CP866: [char; 128] = [
+    '\u{0410}', '\u{0411}', '\u{0412}', '\u{0413}', '\u{0414}', '\u{0415}',
+    '\u{0416}', '\u{0417}', '\u{0418}', '\u{0419}', '\u{041A}', '\u{041B}',
+    '\u{041C}', '\u{041D}', '\u{041E}', '\u{041F}', '\u{0420}', '\u{0421}',
+    '\u{0422}', '\u{0423}', '\u{0424}', '\u{0425}', '\u{0426}', '\u{0427}',
+    '\u{0428}', '\u{0429}', '\u{042A}', '\u{042B}', '\u{042C}', '\u{042D}',
+    '\u{042E}', '\u{042F}', '\u{0430}', '\u{0431}', '\u{0432}', '\u{0433}',
+    '\u{0434}', '\u{0435}', '\u{0436}', '\u{0437}', '\u{0438}', '\u{0439}',
+    '\u{043A}', '\u{043B}', '\u{043C}', '\u{043D}', '\u{043E}', '\u{043F}',
+    '\u{2591}', '\u{2592}', '\u{2593}', '\u{2502}', '\u{2524}', '\u{2561}',
+    '\u{2562}', '\u{2556}', '\u{2555}', '\u{2563}', '\u{2551}', '\u{2557}',
+    '\u{255D}', '\u{255C}', '\u{255B}', '\u{2510}', '\u{2514}', '\u{2534}',
+    '\u{252C}', '\u{251C}', '\u{2500}', '\u{253C}', '\u{255E}', '\u{255F}',
+    '\u{255A}', '\u{2554}', '\u{2569}', '\u{2566}', '\u{2560}', '\u{2550}',
+    '\u{256C}', '\u{2567}', '\u{2568}', '\u{2564}', '\u{2565}', '\u{2559}',
+    '\u{2558}', '\u{2552}', '\u{2553}', '\u{256B}', '\u{256A}', '\u{2518}',
+    '\u{250C}', '\u{2588}', '\u{2584}', '\u{258C}', '\u{2590}', '\u{2580}',
+    '\u{0440}', '\u{0441}', '\u{0442}', '\u{0443}', '\u{0444}', '\u{0445}',
+    '\u{0446}', '\u{0447}', '\u{0448}', '\u{0449}', '\u{044A}', '\u{044B}',
+    '\u{044C}', '\u{044D}', '\u{044E}', '\u{044F}', '\u{0401}', '\u{0451}',
+    '\u{0404}', '\u{0454}', '\u{0407}', '\u{0457}', '\u{040E}', '\u{045E}',
+    '\u{00B0}', '\u{2219}', '\u{00B7}', '\u{221A}', '\u{2116}', '\u{00A4}',
+    '\u{25A0}', '\u{00A0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(samples: &[&[u8]]) -> CodePage {
+        CodePage::Auto.resolve(samples)
+    }
+
+    #[test]
+    fn resolves_cp437() {
+        // MÉSICA.TXT and ÄÜ.TXT: adjacent accented capitals are Latin too
+        assert_eq!(
+            detect(&[b"M\x90SICA.TXT", b"\x8E\x9A.TXT"]),
+            CodePage::Cp437
+        );
+        assert_eq!(detect(&[b"\x8E\x9A.TXT"]), CodePage::Cp437);
+        // CAFÉ.TXT with the comment "Café crème"
+        assert_eq!(
+            detect(&[b"CAF\x90.TXT", b"Caf\x82 cr\x8Ame"]),
+            CodePage::Cp437
+        );
+        assert_eq!(detect(&[b"README.TXT"]), CodePage::Cp437);
+    }
+
+    #[test]
+    fn resolves_cp866() {
+        // ПРИВЕТ.TXT, whose bytes are also Latin letters in other pages
+        let name: &[u8] = b"\x8F\x90\x88\x82\x85\x92.TXT";
+        assert_eq!(detect(&[name]), CodePage::Cp866);
+        // with the comment "привет мир"
+        let comment: &[u8] = b"\xAF\xE0\xA8\xA2\xA5\xE2 \xAC\xA8\xE0";
+        assert_eq!(detect(&[name, comment]), CodePage::Cp866);
+        assert_eq!(CodePage::Cp866.decode(name), "ПРИВЕТ.TXT");
+    }
+
+    #[test]
+    fn resolves_shift_jis() {
+        // 日本語.TXT
+        let kanji: &[u8] = b"\x93\xFA\x96\x7B\x8C\xEA.TXT";
+        assert_eq!(detect(&[kanji]), CodePage::ShiftJis);
+        assert_eq!(CodePage::ShiftJis.decode(kanji), "日本語.TXT");
+        // テスト.TXT: katakana with trail bytes in the ASCII range
+        assert_eq!(
+            detect(&[b"\x83\x65\x83\x58\x83\x67.TXT"]),
+            CodePage::ShiftJis
+        );
+        // A lead byte without its trail byte is not Shift-JIS
+        assert_eq!(score_sjis(b"A\x93"), None);
+    }
+
+    #[test]
+    fn explicit_code_pages_are_kept() {
+        let name: &[u8] = b"\x8F\x90\x88\x82\x85\x92.TXT";
+        assert_eq!(CodePage::Cp437.resolve(&[name]), CodePage::Cp437);
+        assert_eq!(CodePage::parse("IBM866"), Ok(CodePage::Cp866));
+        assert_eq!(CodePage::parse("sjis"), Ok(CodePage::ShiftJis));
+        assert!(CodePage::parse("cp1252").is_err());
+    }
+
+    #[test]
+    fn case_breaks() {
+        let chars: Vec<char> = "ÉcoleéCÉÉc".chars().collect();
+        let breaks: Vec<usize> =
+            (0..chars.len()).filter(|&i| case_break(&chars, i)).collect();
+        // É|c is a capital, e|é and É|É agree; é|C and É|c break
+        assert_eq!(breaks, [6, 9]);
+    }
+}
@@ -0,0 +1,242 @@
+// UnQuantum - comparing archive contents with files on disk (--compare)
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Each entry is decoded and compared with the file extraction would have
+// written, so the name mapping options (-d, --lowercase, ...) apply as they
+// do to -x. The outcome is one of:
+//
+//   identical        same contents and, within the two-second resolution of
+//                    DOS times, the same modification time
+//   differs          different contents, from the first differing offset
+//   missing          no file at that path
+//   different mtime  same contents, different modification time
+//   unreadable       the file is there but could not be read
+//
+// Files below the directory that no archive of the run accounts for are
+// reported once at the end, so an install spread over several archives is
+// checked as a whole. The run owns the record of which files that is (see
+// `Claimed`) and hands it to every archive it compares.
+
+use crate::dostime;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How an archived file compares with its copy on disk
+pub enum Outcome {
+    Identical,
+    /// Contents differ from `offset` on; the sizes are reported when the
+    /// shorter file is a prefix of the longer one
+    Differs { offset: u64, disk_size: u64 },
+    Missing,
+    /// Something other than a file is in the way
+    NotAFile,
+    /// Same contents; the disk copy is `seconds` newer (older if negative)
+    Mtime { seconds: i64 },
+    /// The file could not be read, so nothing is known about its contents
+    Unreadable(io::Error),
+}
+
+impl Outcome {
+    pub fn is_identical(&self) -> bool {
+        matches!(self, Outcome::Identical)
+    }
+
+    /// The outcome as shown after the entry's name
+    pub fn describe(&self, archived_size: u32) -> String {
+        match self {
+            Outcome::Identical => "identical".to_string(),
+            Outcome::Differs { offset, disk_size }
+                if *disk_size != archived_size as u64 =>
+            {
+                format!(
+                    "differs at offset {} ({} bytes on disk, {} in archive)",
+                    offset, disk_size, archived_size
+                )
+            }
+            Outcome::Differs { offset, .. } => {
+                format!("differs at offset {}", offset)
+            }
+            Outcome::Missing => "missing".to_string(),
+            Outcome::NotAFile => "missing (not a file on disk)".to_string(),
+            Outcome::Unreadable(e) => format!("unreadable ({})", e),
+            Outcome::Mtime { seconds } => format!(
+                "different mtime (disk copy {} s {})",
+                seconds.unsigned_abs(),
+                if *seconds > 0 { "newer" } else { "older" }
+            ),
+        }
+    }
+}
+
+/// Compare `data`, archived with the time `archived` (Unix seconds), with
+/// the file at `path`
+pub fn compare(path: Option<&Path>, data: &[u8], archived: i64) -> Outcome {
+    let Some(path) = path else {
+        return Outcome::Missing;
+    };
+    let meta = match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => meta,
+        Ok(_) => return Outcome::NotAFile,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Outcome::Missing,
+        Err(e) => return Outcome::Unreadable(e),
+    };
+    let disk = match fs::read(path) {
+        Ok(disk) => disk,
+        Err(e) => return Outcome::Unreadable(e),
+    };
+    let offset = disk
+        .iter()
+        .zip(data)
+        .position(|(a, b)| a != b)
+        .unwrap_or(disk.len().min(data.len()));
+    if offset < disk.len() || offset < data.len() {
+        return Outcome::Differs {
+            offset: offset as u64,
+            disk_size: disk.len() as u64,
+        };
+    }
+    match meta.modified() {
+        Ok(mtime) => {
+            let seconds = dostime::unix_seconds(mtime) - archived;
+            if seconds.abs() >= dostime::DOS_RESOLUTION {
+                Outcome::Mtime { seconds }
+            } else {
+                Outcome::Identical
+            }
+        }
+        // No usable time on disk: the contents are all there is to compare
+        Err(_) => Outcome::Identical,
+    }
+}
+
+/// The file at `rel` below `base`. With `fold_case`, a name differing only
+/// in case also matches, as it would on DOS.
+pub fn find(base: &Path, rel: &Path, fold_case: bool) -> Option<PathBuf> {
+    let exact = base.join(rel);
+    if exact.exists() {
+        return Some(exact);
+    }
+    if !fold_case {
+        return None;
+    }
+    let mut path = base.to_path_buf();
+    for part in rel.components() {
+        let part = part.as_os_str();
+        let found = fs::read_dir(&path).ok()?.filter_map(|e| e.ok()).find(
+            |entry| entry.file_name().eq_ignore_ascii_case(part),
+        )?;
+        path.push(found.file_name());
+    }
+    Some(path)
+}
+
+/// A path as the set of claimed paths stores it
+fn key(path: &Path, fold_case: bool) -> String {
+    let path = path.to_string_lossy();
+    if fold_case {
+        path.to_lowercase()
+    } else {
+        path.into_owned()
+    }
+}
+
+/// Paths of every entry of the archives compared so far, found on disk or
+/// not, keyed as by `key`. Workers (-j) share one.
+#[derive(Default)]
+pub struct Claimed {
+    paths: Mutex<HashSet<String>>,
+}
+
+impl Claimed {
+    /// Record that an archive entry accounts for the file at `path`
+    pub fn claim(&self, path: &Path, fold_case: bool) {
+        self.paths.lock().unwrap().insert(key(path, fold_case));
+    }
+
+    /// Files below `root` that no claimed path accounts for, relative to
+    /// `root` and sorted. Symlinked directories are not followed.
+    pub fn extras(&self, root: &Path, fold_case: bool) -> Vec<PathBuf> {
+        let claimed = self.paths.lock().unwrap();
+        let mut found = Vec::new();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                match entry.file_type() {
+                    Ok(t) if t.is_dir() => dirs.push(path),
+                    Ok(_) if !claimed.contains(&key(&path, fold_case)) => {
+                        let rel = path.strip_prefix(root).unwrap_or(&path);
+                        found.push(rel.to_path_buf());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        found.sort();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let dir = std::env::temp_dir()
+            .join(format!("unquantum-compare-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("SUB")).unwrap();
+        let file = dir.join("SUB").join("Readme.txt");
+        fs::write(&file, b"hello world").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let mtime = dostime::unix_seconds(modified);
+
+        let same = compare(Some(&file), b"hello world", mtime);
+        assert!(same.is_identical());
+        assert!(compare(Some(&file), b"hello world", mtime - 1).is_identical());
+        assert!(matches!(
+            compare(Some(&file), b"hello world", mtime - 100),
+            Outcome::Mtime { seconds: 100 }
+        ));
+        let differs = compare(Some(&file), b"hello there", mtime);
+        assert!(matches!(
+            differs,
+            Outcome::Differs { offset: 6, disk_size: 11 }
+        ));
+        assert_eq!(differs.describe(11), "differs at offset 6");
+        let longer = compare(Some(&file), b"hello world!", mtime);
+        assert_eq!(
+            longer.describe(12),
+            "differs at offset 11 (11 bytes on disk, 12 in archive)"
+        );
+        assert!(matches!(compare(None, b"", 0), Outcome::Missing));
+        assert!(matches!(
+            compare(Some(&dir.join("SUB")), b"", 0),
+            Outcome::NotAFile
+        ));
+
+        let denied = Outcome::Unreadable(ErrorKind::PermissionDenied.into());
+        assert_eq!(denied.describe(0), "unreadable (permission denied)");
+
+        let claimed = Claimed::default();
+        claimed.claim(&dir.join("sub/readme.TXT"), true);
+        fs::write(dir.join("EXTRA.TXT"), b"").unwrap();
+        assert_eq!(claimed.extras(&dir, true), [Path::new("EXTRA.TXT")]);
+        assert_eq!(claimed.extras(&dir, false).len(), 2);
+
+        let rel = Path::new("sub/README.TXT");
+        assert_eq!(find(&dir, rel, true), Some(file.clone()));
+        assert_eq!(find(&dir, Path::new("SUB/Readme.txt"), false), Some(file));
+        assert_eq!(find(&dir, Path::new("SUB/OTHER.TXT"), true), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
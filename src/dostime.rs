@@ -0,0 +1,463 @@
+// UnQuantum - DOS date/time conversion
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// DOS stores timestamps as two 16-bit words in local time, with no time zone
+// and a resolution of two seconds:
+//   date: bits 15-9 year - 1980, bits 8-5 month, bits 4-0 day
+//   time: bits 15-11 hours, bits 10-5 minutes, bits 4-0 seconds / 2
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Resolution of DOS timestamps, in seconds
+pub const DOS_RESOLUTION: i64 = 2;
+
+/// How to interpret archived DOS timestamps
+#[derive(Clone, Copy, PartialEq)]
+pub enum TimeZone {
+    /// The host's local time zone, as DOS itself did
+    Local,
+    Utc,
+}
+
+/// How listings write dates
+#[derive(Clone, Copy, PartialEq)]
+pub enum DateFormat {
+    /// DD-MM-YYYY, as UnQuantum always has
+    Dmy,
+    /// MM-DD-YYYY
+    Mdy,
+    /// YYYY-MM-DD
+    Iso,
+    /// The date format of the user's locale (LC_TIME), as `date +%x` shows
+    Locale,
+}
+
+impl DateFormat {
+    pub fn parse(name: &str) -> Result<DateFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmy" => Ok(DateFormat::Dmy),
+            "mdy" => Ok(DateFormat::Mdy),
+            "iso" => Ok(DateFormat::Iso),
+            "locale" => Ok(DateFormat::Locale),
+            _ => Err(format!(
+                "Unknown date format '{}' (known: dmy, mdy, iso, locale)",
+                name
+            )),
+        }
+    }
+
+    /// Write a date. The fields are taken as stored, so a zero day or month
+    /// in an archive shows as such instead of being corrected.
+    pub fn format(self, year: u16, month: u8, day: u8) -> String {
+        match self {
+            DateFormat::Dmy => format!("{:02}-{:02}-{:04}", day, month, year),
+            DateFormat::Mdy => format!("{:02}-{:02}-{:04}", month, day, year),
+            DateFormat::Iso => format!("{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::Locale => locale_date(year, month, day)
+                .unwrap_or_else(|| DateFormat::Iso.format(year, month, day)),
+        }
+    }
+}
+
+/// A broken-down DOS timestamp
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub struct DosDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DosDateTime {
+    /// Unpack DOS date and time words. Out-of-range fields, which some
+    /// archivers wrote for unset dates, are clamped to the nearest valid
+    /// value so that conversion never fails.
+    pub fn from_dos(date: u16, time: u16) -> Self {
+        let year = ((date >> 9) & 0x7F) + 1980;
+        let month = ((date >> 5) & 0x0F).clamp(1, 12) as u8;
+        let day = (date & 0x1F).clamp(1, days_in_month(year, month) as u16);
+        DosDateTime {
+            year,
+            month,
+            day: day as u8,
+            hour: ((time >> 11) & 0x1F).min(23) as u8,
+            minute: ((time >> 5) & 0x3F).min(59) as u8,
+            second: ((time & 0x1F) * 2).min(58) as u8,
+        }
+    }
+
+    /// Parse `YYYY-MM-DD`, optionally followed by `THH:MM[:SS]` or a space
+    /// and the time
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!("Invalid date '{}' (expected YYYY-MM-DD[THH:MM[:SS]])", s)
+        };
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None),
+        };
+        let fields = |text: &str, sep: char| -> Result<Vec<u16>, String> {
+            text.split(sep)
+                .map(|f| f.parse::<u16>().map_err(|_| invalid()))
+                .collect()
+        };
+        let d = fields(date, '-')?;
+        if d.len() != 3 || d[0] == 0 {
+            return Err(invalid());
+        }
+        if !(1..=12).contains(&d[1])
+            || d[2] < 1
+            || d[2] > days_in_month(d[0], d[1] as u8) as u16
+        {
+            return Err(invalid());
+        }
+        let (year, month, day) = (d[0], d[1] as u8, d[2] as u8);
+        let t = match time {
+            Some(time) => fields(time, ':')?,
+            None => vec![0, 0],
+        };
+        if !(2..=3).contains(&t.len())
+            || t[0] > 23
+            || t[1] > 59
+            || t.get(2).is_some_and(|&s| s > 59)
+        {
+            return Err(invalid());
+        }
+        Ok(DosDateTime {
+            year,
+            month,
+            day,
+            hour: t[0] as u8,
+            minute: t[1] as u8,
+            second: t.get(2).copied().unwrap_or(0) as u8,
+        })
+    }
+
+    /// ISO 8601 form. DOS times carry no zone, so only UTC times get one.
+    pub fn iso8601(self, tz: TimeZone) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            if tz == TimeZone::Utc { "Z" } else { "" }
+        )
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn to_unix(self, tz: TimeZone) -> i64 {
+        match tz {
+            TimeZone::Utc => self.to_unix_utc(),
+            TimeZone::Local => {
+                local_to_unix(&self).unwrap_or_else(|| self.to_unix_utc())
+            }
+        }
+    }
+
+    fn to_unix_utc(self) -> i64 {
+        let days = days_from_civil(
+            self.year as i64,
+            self.month as i64,
+            self.day as i64,
+        );
+        days * 86400
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    pub fn to_system_time(self, tz: TimeZone) -> SystemTime {
+        let secs = self.to_unix(tz);
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        }
+    }
+}
+
+fn is_leap(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100))
+        || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+/// (Howard Hinnant's days_from_civil algorithm)
+pub fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Seconds since the Unix epoch of a file time
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+// struct tm as the C library declares it, for strftime() and, on Windows,
+// _mktime64(). BSD, macOS and the Linux C libraries end it with tm_gmtoff
+// (a long) and tm_zone (a pointer), both pointer-sized on those systems;
+// elsewhere, MSVC included, it ends at tm_isdst.
+#[repr(C)]
+struct Tm {
+    tm_sec: i32,
+    tm_min: i32,
+    tm_hour: i32,
+    tm_mday: i32,
+    tm_mon: i32,
+    tm_year: i32,
+    tm_wday: i32,
+    tm_yday: i32,
+    tm_isdst: i32,
+    /// tm_gmtoff and tm_zone where they exist, left zero
+    tm_tail: [usize; TM_TAIL_WORDS],
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+const TM_TAIL_WORDS: usize = 2;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+const TM_TAIL_WORDS: usize = 0;
+
+#[cfg(windows)]
+extern "C" {
+    /// The 64-bit time_t mktime() of the Microsoft C runtime
+    #[link_name = "_mktime64"]
+    fn mktime(tm: *mut Tm) -> i64;
+}
+
+#[cfg(any(unix, windows))]
+extern "C" {
+    fn setlocale(
+        category: std::os::raw::c_int,
+        locale: *const std::os::raw::c_char,
+    ) -> *mut std::os::raw::c_char;
+    fn strftime(
+        s: *mut std::os::raw::c_char,
+        max: usize,
+        format: *const std::os::raw::c_char,
+        tm: *const Tm,
+    ) -> usize;
+}
+
+/// LC_TIME is numbered differently by glibc/musl/bionic and by the BSD,
+/// macOS and Windows C libraries
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const LC_TIME: std::os::raw::c_int = 2;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const LC_TIME: std::os::raw::c_int = 5;
+
+/// Take date formats from the environment (LC_ALL, LC_TIME, LANG) instead
+/// of the C locale. Call before any threads are started: the C library's
+/// locale is process-wide.
+#[cfg(any(unix, windows))]
+pub fn use_locale_dates() {
+    // SAFETY: "" is a valid C string; nothing else uses the locale yet
+    unsafe {
+        setlocale(LC_TIME, c"".as_ptr());
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn use_locale_dates() {}
+
+/// A date in the locale's format (strftime's %x), or None when the C library
+/// cannot format it
+#[cfg(any(unix, windows))]
+fn locale_date(year: u16, month: u8, day: u8) -> Option<String> {
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let tm = Tm {
+        tm_sec: 0,
+        tm_min: 0,
+        tm_hour: 0,
+        tm_mday: day as i32,
+        tm_mon: month as i32 - 1,
+        tm_year: year as i32 - 1900,
+        tm_wday: (days + 4).rem_euclid(7) as i32, // 1970-01-01 was a Thursday
+        tm_yday: (days - days_from_civil(year as i64, 1, 1)) as i32,
+        tm_isdst: 0,
+        tm_tail: [0; TM_TAIL_WORDS],
+    };
+    let mut buf = [0u8; 64];
+    // SAFETY: buf is writable for buf.len() bytes, the format is a valid C
+    // string and tm is fully initialised
+    let len = unsafe {
+        strftime(buf.as_mut_ptr().cast(), buf.len(), c"%x".as_ptr(), &tm)
+    };
+    if len == 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn locale_date(_year: u16, _month: u8, _day: u8) -> Option<String> {
+    None
+}
+
+/// Convert a local date/time to Unix seconds, or None when the local time
+/// zone is unknown
+#[cfg(not(windows))]
+fn local_to_unix(dt: &DosDateTime) -> Option<i64> {
+    crate::tzfile::local().map(|zone| zone.local_to_utc(dt.to_unix_utc()))
+}
+
+/// Convert a local date/time to Unix seconds, or None when the C runtime
+/// cannot represent it. Windows has no TZif files, so the C runtime does
+/// the time zone and DST lookup.
+#[cfg(windows)]
+fn local_to_unix(dt: &DosDateTime) -> Option<i64> {
+    let mut tm = Tm {
+        tm_sec: dt.second as i32,
+        tm_min: dt.minute as i32,
+        tm_hour: dt.hour as i32,
+        tm_mday: dt.day as i32,
+        tm_mon: dt.month as i32 - 1,
+        tm_year: dt.year as i32 - 1900,
+        tm_wday: 0,
+        tm_yday: 0,
+        tm_isdst: -1, // let the C runtime decide whether DST applies
+        tm_tail: [0; TM_TAIL_WORDS],
+    };
+    // SAFETY: tm is a valid, fully initialised struct tm that outlives the
+    // call
+    let secs = unsafe { mktime(&mut tm) };
+    if secs == -1 {
+        None
+    } else {
+        Some(secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd_hms(dt: DosDateTime) -> (u16, u8, u8, u8, u8, u8) {
+        (dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second)
+    }
+
+    #[test]
+    fn parse_dates() {
+        let parse = |s| DosDateTime::parse(s).map(ymd_hms);
+        assert_eq!(parse("1994-06-15"), Ok((1994, 6, 15, 0, 0, 0)));
+        assert_eq!(parse("1994-06-15T13:45"), Ok((1994, 6, 15, 13, 45, 0)));
+        assert_eq!(parse("2000-02-29 23:59:59"), Ok((2000, 2, 29, 23, 59, 59)));
+        for bad in [
+            "",
+            "1994-06",
+            "1994-6-15x",
+            "0-01-01",
+            "1900-02-29",
+            "1994-04-31",
+            "1994-13-01",
+            "1994-00-10",
+            // 268 and 257 wrap to valid values as bytes
+            "1994-268-01",
+            "1994-01-257",
+            "1994-06-15T24:00",
+            "1994-06-15T12",
+            "1994-06-15T12:60",
+            "1994-06-15T12:00:60",
+            "1994-06-15T12:00:00:00",
+        ] {
+            assert!(DosDateTime::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn dates_order_chronologically() {
+        let a = DosDateTime::parse("1994-06-15T13:45").unwrap();
+        let b = DosDateTime::parse("1994-06-15T13:45:02").unwrap();
+        let c = DosDateTime::parse("1995-01-01").unwrap();
+        assert!(a < b && b < c);
+    }
+
+    #[test]
+    fn unpack_dos_words() {
+        let date = (14 << 9) | (6 << 5) | 15;
+        let time = (13 << 11) | (45 << 5) | 10;
+        let dt = DosDateTime::from_dos(date, time);
+        assert_eq!(ymd_hms(dt), (1994, 6, 15, 13, 45, 20));
+        assert_eq!(dt.iso8601(TimeZone::Utc), "1994-06-15T13:45:20Z");
+        assert_eq!(dt.iso8601(TimeZone::Local), "1994-06-15T13:45:20");
+    }
+
+    #[test]
+    fn out_of_range_words_are_clamped() {
+        // Unset dates: month and day 0
+        let dt = DosDateTime::from_dos(0, 0);
+        assert_eq!(ymd_hms(dt), (1980, 1, 1, 0, 0, 0));
+        // Hour 31, minute 63, second 62, February 30th
+        let dt = DosDateTime::from_dos((1 << 9) | (2 << 5) | 30, 0xFFFF);
+        assert_eq!(ymd_hms(dt), (1981, 2, 28, 23, 59, 58));
+        let dt = DosDateTime::from_dos((20 << 9) | (2 << 5) | 31, 0);
+        assert_eq!(ymd_hms(dt), (2000, 2, 29, 0, 0, 0));
+        let dt = DosDateTime::from_dos(0xFFFF, 0);
+        assert_eq!(ymd_hms(dt), (2107, 12, 31, 0, 0, 0));
+    }
+
+    #[test]
+    fn unix_times() {
+        let utc = |s| DosDateTime::parse(s).unwrap().to_unix(TimeZone::Utc);
+        assert_eq!(utc("1980-01-01"), 315_532_800);
+        assert_eq!(utc("2000-02-29T12:00"), 951_825_600);
+        assert_eq!(utc("2107-12-31T23:59:58"), 4_354_819_198);
+        let t = DosDateTime::parse("1994-06-15").unwrap();
+        assert_eq!(
+            unix_seconds(t.to_system_time(TimeZone::Utc)),
+            t.to_unix(TimeZone::Utc)
+        );
+    }
+
+    #[test]
+    fn date_formats() {
+        assert_eq!(DateFormat::Dmy.format(1994, 6, 5), "05-06-1994");
+        assert_eq!(DateFormat::Mdy.format(1994, 6, 5), "06-05-1994");
+        assert_eq!(DateFormat::Iso.format(1994, 6, 5), "1994-06-05");
+        // Stored fields are shown as they are
+        assert_eq!(DateFormat::Iso.format(1980, 0, 0), "1980-00-00");
+        assert!(DateFormat::parse("ISO") == Ok(DateFormat::Iso));
+        assert!(DateFormat::parse("ymd").is_err());
+    }
+}
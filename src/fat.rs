@@ -0,0 +1,461 @@
+// UnQuantum - read-only FAT12/FAT16 disk image reader
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Most surviving Quantum archives live on raw floppy dumps (.img/.ima) or on
+// hard-disk images. This module walks the FAT file system inside such images
+// so the archives can be processed without loop-mounting them.
+//
+// Supported layouts:
+// - Superfloppy images (boot sector with a BPB at offset 0)
+// - MBR-partitioned hard-disk images (primary FAT12/FAT16 partitions)
+// - Early DOS 1.x floppies without a BPB, recognised by image size
+//
+// FAT32, extended partitions and long file names are not supported; long
+// file name entries are skipped and the 8.3 alias is used instead.
+
+use crate::codepage::CodePage;
+use std::collections::HashSet;
+
+/// Boot sector / MBR signature at offset 510
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// MBR partition types that hold a FAT12 or FAT16 file system
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0E, 0x11, 0x14];
+
+/// Directory entry attribute bits
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// Directory nesting limit. Cycles are caught by never entering a directory
+/// twice; this bounds the recursion of very deep trees.
+const MAX_DIR_DEPTH: usize = 32;
+
+/// BPB-less DOS 1.x floppy geometries, keyed by image size:
+/// (image size, sectors per cluster, root entries, sectors per FAT)
+const DOS1_GEOMETRIES: [(usize, u8, u16, u16); 4] = [
+    (163_840, 1, 64, 1),  // 160 KB, single sided, 8 sectors/track
+    (184_320, 1, 64, 2),  // 180 KB, single sided, 9 sectors/track
+    (327_680, 2, 112, 1), // 320 KB, double sided, 8 sectors/track
+    (368_640, 2, 112, 2), // 360 KB, double sided, 9 sectors/track
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum FatType {
+    Fat12,
+    Fat16,
+}
+
+/// A mounted (read-only) FAT12/FAT16 volume inside a disk image
+pub struct FatVolume<'a> {
+    /// Volume bytes, starting at the boot sector
+    data: &'a [u8],
+    /// Partition number (1-4) for MBR images, None for superfloppies
+    pub partition: Option<usize>,
+    fat_type: FatType,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    fat_offset: usize,
+    root_offset: usize,
+    root_entries: usize,
+    data_offset: usize,
+    cluster_count: usize,
+}
+
+/// A regular file found while walking a volume
+pub struct FatFile {
+    /// DOS path using backslash separators, e.g. `\GAMES\DATA.Q`
+    pub path: String,
+    pub size: u32,
+    first_cluster: usize,
+}
+
+impl<'a> FatVolume<'a> {
+    /// Mount the volume whose boot sector is at the start of `data`
+    fn open(data: &'a [u8], partition: Option<usize>) -> Result<Self, String> {
+        if data.len() < 512 {
+            return Err("Volume is too small to hold a boot sector".to_string());
+        }
+
+        let bytes_per_sector = u16_at(data, 11) as usize;
+        let sectors_per_cluster = data[13] as usize;
+        let reserved_sectors = u16_at(data, 14) as usize;
+        let num_fats = data[16] as usize;
+        let root_entries = u16_at(data, 17) as usize;
+        let total_sectors_16 = u16_at(data, 19) as usize;
+        let sectors_per_fat = u16_at(data, 22) as usize;
+        let total_sectors_32 = u32_at(data, 32) as usize;
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || !(1..=4).contains(&num_fats)
+            || root_entries == 0
+            || sectors_per_fat == 0
+        {
+            return Err("No valid FAT12/FAT16 BIOS parameter block".to_string());
+        }
+
+        let total_sectors = if total_sectors_16 != 0 {
+            total_sectors_16
+        } else {
+            total_sectors_32
+        };
+        let root_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        let first_data_sector =
+            reserved_sectors + num_fats * sectors_per_fat + root_sectors;
+        if total_sectors <= first_data_sector {
+            return Err("FAT volume has no data area".to_string());
+        }
+        let cluster_count =
+            (total_sectors - first_data_sector) / sectors_per_cluster;
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            return Err("FAT32 volumes are not supported".to_string());
+        };
+
+        Ok(FatVolume {
+            data,
+            partition,
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_offset: reserved_sectors * bytes_per_sector,
+            root_offset: (reserved_sectors + num_fats * sectors_per_fat)
+                * bytes_per_sector,
+            root_entries,
+            data_offset: first_data_sector * bytes_per_sector,
+            cluster_count,
+        })
+    }
+
+    /// Mount a DOS 1.x floppy that predates the BPB, using its image size
+    fn open_dos1(data: &'a [u8]) -> Option<Self> {
+        let &(size, spc, root_entries, spf) =
+            DOS1_GEOMETRIES.iter().find(|g| g.0 == data.len())?;
+        let root_offset = 512 * (1 + 2 * spf as usize);
+        let data_offset = root_offset + root_entries as usize * 32;
+        Some(FatVolume {
+            data,
+            partition: None,
+            fat_type: FatType::Fat12,
+            bytes_per_sector: 512,
+            sectors_per_cluster: spc as usize,
+            fat_offset: 512,
+            root_offset,
+            root_entries: root_entries as usize,
+            data_offset,
+            cluster_count: (size - data_offset) / (512 * spc as usize),
+        })
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// Read the FAT entry for a cluster
+    fn fat_entry(&self, cluster: usize) -> Option<usize> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let off = self.fat_offset + cluster + cluster / 2;
+                if off + 2 > self.data.len() {
+                    return None;
+                }
+                let pair = u16_at(self.data, off) as usize;
+                Some(if cluster & 1 == 1 { pair >> 4 } else { pair & 0xFFF })
+            }
+            FatType::Fat16 => {
+                let off = self.fat_offset + cluster * 2;
+                if off + 2 > self.data.len() {
+                    return None;
+                }
+                Some(u16_at(self.data, off) as usize)
+            }
+        }
+    }
+
+    /// Follow a cluster chain, stopping at end-of-chain markers, bad
+    /// clusters, out-of-range values and loops.
+    fn cluster_chain(&self, first: usize) -> Vec<usize> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster >= 2
+            && cluster < self.cluster_count + 2
+            && chain.len() <= self.cluster_count
+        {
+            chain.push(cluster);
+            cluster = match self.fat_entry(cluster) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        chain
+    }
+
+    /// Concatenate the data of a cluster chain, clipped to the image
+    fn read_chain(&self, first: usize, limit: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        let csize = self.cluster_size();
+        for cluster in self.cluster_chain(first) {
+            if out.len() >= limit {
+                break;
+            }
+            let start = self.data_offset + (cluster - 2) * csize;
+            if start >= self.data.len() {
+                break;
+            }
+            let end = (start + csize).min(self.data.len());
+            out.extend_from_slice(&self.data[start..end]);
+        }
+        out.truncate(limit);
+        out
+    }
+
+    /// Read the contents of a file found by `files()`
+    pub fn read_file(&self, file: &FatFile) -> Result<Vec<u8>, String> {
+        let data = self.read_chain(file.first_cluster, file.size as usize);
+        if data.len() < file.size as usize {
+            return Err(format!(
+                "{}: cluster chain holds {} of {} bytes",
+                file.path,
+                data.len(),
+                file.size
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Read up to `len` leading bytes of a file, for signature sniffing
+    pub fn read_head(&self, file: &FatFile, len: usize) -> Vec<u8> {
+        self.read_chain(file.first_cluster, len.min(file.size as usize))
+    }
+
+    /// List every regular file on the volume, depth first in directory order
+    pub fn files(&self, codepage: CodePage) -> Vec<FatFile> {
+        let mut files = Vec::new();
+        let root_end =
+            (self.root_offset + self.root_entries * 32).min(self.data.len());
+        if self.root_offset < root_end {
+            let root = &self.data[self.root_offset..root_end];
+            let mut visited = HashSet::new();
+            self.walk_dir(root, "", 0, codepage, &mut visited, &mut files);
+        }
+        files
+    }
+
+    /// Collect the files below `dir`. `visited` holds the first clusters of
+    /// the directories entered so far: a damaged or crafted image can point
+    /// several entries, or a directory's own child, at one directory, and
+    /// each is walked only once.
+    fn walk_dir(
+        &self,
+        dir: &[u8],
+        prefix: &str,
+        depth: usize,
+        codepage: CodePage,
+        visited: &mut HashSet<usize>,
+        files: &mut Vec<FatFile>,
+    ) {
+        for entry in dir.chunks_exact(32) {
+            match entry[0] {
+                0x00 => break,       // end of directory
+                0xE5 => continue,    // deleted entry
+                b'.' => continue,    // "." and ".."
+                _ => {}
+            }
+            let attr = entry[11];
+            if attr & ATTR_LONG_NAME == ATTR_LONG_NAME
+                || attr & ATTR_VOLUME_ID != 0
+            {
+                continue;
+            }
+
+            let path = format!("{}\\{}", prefix, short_name(entry, codepage));
+            let first_cluster = u16_at(entry, 26) as usize;
+            if attr & ATTR_DIRECTORY != 0 {
+                if depth < MAX_DIR_DEPTH
+                    && first_cluster >= 2
+                    && visited.insert(first_cluster)
+                {
+                    let sub = self.read_chain(first_cluster, usize::MAX);
+                    self.walk_dir(
+                        &sub,
+                        &path,
+                        depth + 1,
+                        codepage,
+                        visited,
+                        files,
+                    );
+                }
+            } else {
+                files.push(FatFile {
+                    path,
+                    size: u32_at(entry, 28),
+                    first_cluster,
+                });
+            }
+        }
+    }
+}
+
+/// Format an 8.3 directory entry name as `NAME.EXT`
+fn short_name(entry: &[u8], codepage: CodePage) -> String {
+    let mut raw = entry[..11].to_vec();
+    // 0x05 stands for a leading 0xE5 byte (a valid code page 437 character)
+    if raw[0] == 0x05 {
+        raw[0] = 0xE5;
+    }
+    let base = codepage.decode(&raw[..8]);
+    let ext = codepage.decode(&raw[8..]);
+    let base = base.trim_end();
+    let ext = ext.trim_end();
+    if ext.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+/// Check whether a sector carries a plausible FAT boot sector
+fn has_bpb(sector: &[u8]) -> bool {
+    // x86 jump instruction at the start of every DOS 2.0+ boot sector
+    (sector[0] == 0xEB || sector[0] == 0xE9)
+        && matches!(u16_at(sector, 11), 512 | 1024 | 2048 | 4096)
+}
+
+/// Quick check for a disk image, used to tell images apart from archives
+pub fn looks_like_image(data: &[u8]) -> bool {
+    if DOS1_GEOMETRIES.iter().any(|g| g.0 == data.len()) {
+        return true;
+    }
+    data.len() >= 512 && data[510..512] == BOOT_SIGNATURE
+}
+
+/// Mount every FAT12/FAT16 volume in a floppy or hard-disk image
+pub fn open_image(data: &[u8]) -> Result<Vec<FatVolume<'_>>, String> {
+    if data.len() < 512 {
+        return Err("Image is too small to hold a boot sector".to_string());
+    }
+
+    // A BPB in sector 0 means a partitionless (superfloppy) volume
+    if has_bpb(data) {
+        return FatVolume::open(data, None).map(|v| vec![v]);
+    }
+
+    if data[510..512] == BOOT_SIGNATURE {
+        let mut volumes = Vec::new();
+        let mut errors = Vec::new();
+        for part in 0..4 {
+            let entry = &data[446 + part * 16..446 + (part + 1) * 16];
+            let part_type = entry[4];
+            let start = u32_at(entry, 8) as usize * 512;
+            if part_type == 0 {
+                continue;
+            }
+            if !FAT_PARTITION_TYPES.contains(&part_type) {
+                errors.push(format!(
+                    "partition {}: unsupported type 0x{:02X}",
+                    part + 1,
+                    part_type
+                ));
+                continue;
+            }
+            if start >= data.len() {
+                errors.push(format!(
+                    "partition {}: starts beyond the end of the image",
+                    part + 1
+                ));
+                continue;
+            }
+            match FatVolume::open(&data[start..], Some(part + 1)) {
+                Ok(v) => volumes.push(v),
+                Err(e) => errors.push(format!("partition {}: {}", part + 1, e)),
+            }
+        }
+        if volumes.is_empty() {
+            return Err(if errors.is_empty() {
+                "MBR partition table lists no partitions".to_string()
+            } else {
+                format!("No usable FAT partition ({})", errors.join("; "))
+            });
+        }
+        return Ok(volumes);
+    }
+
+    FatVolume::open_dos1(data)
+        .map(|v| vec![v])
+        .ok_or_else(|| "Not a FAT12/FAT16 disk image".to_string())
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    (data[pos] as u16) | ((data[pos + 1] as u16) << 8)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    (data[pos] as u32)
+        | ((data[pos + 1] as u32) << 8)
+        | ((data[pos + 2] as u32) << 16)
+        | ((data[pos + 3] as u32) << 24)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BPB-less 160 KB floppy: FAT at 512, root directory at 1536, data
+    /// (one sector per cluster) at 3584
+    const ROOT: usize = 1536;
+    const DATA: usize = 3584;
+
+    fn dir_entry(
+        image: &mut [u8],
+        at: usize,
+        name: &[u8; 11],
+        attr: u8,
+        cluster: u16,
+        size: u32,
+    ) {
+        image[at..at + 11].copy_from_slice(name);
+        image[at + 11] = attr;
+        image[at + 26..at + 28].copy_from_slice(&cluster.to_le_bytes());
+        image[at + 28..at + 32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    fn paths(image: &[u8]) -> Vec<String> {
+        let volumes = open_image(image).unwrap();
+        let files = volumes[0].files(CodePage::Cp437);
+        files.into_iter().map(|f| f.path).collect()
+    }
+
+    #[test]
+    fn directory_cycles_are_walked_once() {
+        let mut image = vec![0u8; 163_840];
+        // Cluster 2 is a one-cluster directory
+        image[512 + 3..512 + 5].copy_from_slice(&[0xFF, 0x0F]);
+        dir_entry(&mut image, ROOT, b"GAME       ", ATTR_DIRECTORY, 2, 0);
+        dir_entry(&mut image, ROOT + 32, b"README  TXT", 0, 0, 0);
+        // GAME holds a file and sixteen subdirectories that are GAME itself:
+        // followed blindly, that is 16^32 walks
+        dir_entry(&mut image, DATA, b"DATA    Q  ", 0, 0, 0);
+        for n in 1..=16 {
+            let mut name = *b"LOOP       ";
+            name[4] = b'A' + n as u8;
+            dir_entry(&mut image, DATA + n * 32, &name, ATTR_DIRECTORY, 2, 0);
+        }
+        assert_eq!(paths(&image), ["\\GAME\\DATA.Q", "\\README.TXT"]);
+    }
+
+    #[test]
+    fn skips_deleted_and_volume_entries() {
+        let mut image = vec![0u8; 163_840];
+        dir_entry(&mut image, ROOT, b"LABEL      ", ATTR_VOLUME_ID, 0, 0);
+        dir_entry(&mut image, ROOT + 32, b"\xE5OLD    Q  ", 0, 0, 0);
+        dir_entry(&mut image, ROOT + 64, b"\x05NEW    Q  ", 0, 0, 10);
+        assert_eq!(paths(&image), ["\\\u{03C3}NEW.Q"]);
+    }
+}
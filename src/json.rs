@@ -0,0 +1,188 @@
+// UnQuantum - minimal JSON values for machine-readable output
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Only what the reports need: building values and writing them, compact
+// (one line, for ndjson) or indented. Object keys keep their insertion
+// order so that output is stable.
+
+use std::fmt::Write;
+
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+impl Value {
+    /// An object from key/value pairs
+    pub fn object(fields: Vec<(&'static str, Value)>) -> Value {
+        Value::Object(fields)
+    }
+
+    pub fn string(s: impl Into<String>) -> Value {
+        Value::String(s.into())
+    }
+
+    /// Append fields to an object; no effect on other values
+    pub fn extend(&mut self, more: Vec<(&'static str, Value)>) {
+        if let Value::Object(fields) = self {
+            fields.extend(more);
+        }
+    }
+
+    /// Single-line form, as used for ndjson records
+    pub fn to_compact(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, None, 0);
+        out
+    }
+
+    /// Form indented by two spaces per level
+    pub fn to_pretty(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, Some(2), 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: Option<usize>, level: usize) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Int(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            // JSON has no NaN or infinity
+            Value::Float(x) if !x.is_finite() => out.push_str("null"),
+            Value::Float(x) => {
+                let _ = write!(out, "{}", x);
+            }
+            Value::String(s) => write_string(out, s),
+            Value::Array(items) => {
+                write_list(out, '[', ']', items.len(), indent, level, |out, i| {
+                    items[i].write(out, indent, level + 1)
+                });
+            }
+            Value::Object(fields) => {
+                write_list(out, '{', '}', fields.len(), indent, level, |out, i| {
+                    let (key, value) = &fields[i];
+                    write_string(out, key);
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    value.write(out, indent, level + 1);
+                });
+            }
+        }
+    }
+}
+
+/// Write a bracketed list, one element per line when indenting
+fn write_list(
+    out: &mut String,
+    open: char,
+    close: char,
+    len: usize,
+    indent: Option<usize>,
+    level: usize,
+    mut element: impl FnMut(&mut String, usize),
+) {
+    out.push(open);
+    if len == 0 {
+        out.push(close);
+        return;
+    }
+    for i in 0..len {
+        if i > 0 {
+            out.push(',');
+        }
+        if let Some(width) = indent {
+            out.push('\n');
+            out.push_str(&" ".repeat(width * (level + 1)));
+        }
+        element(out, i);
+    }
+    if let Some(width) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(width * level));
+    }
+    out.push(close);
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        let value = Value::string("a\"b\\c\nd\re\tf\u{1}g\u{1f}h\u{7f}é日");
+        assert_eq!(
+            value.to_compact(),
+            "\"a\\\"b\\\\c\\nd\\re\\tf\\u0001g\\u001fh\u{7f}é日\""
+        );
+        // DOS names may hold anything, keys are escaped alike
+        let value = Value::object(vec![("k\"", Value::string("C:\\X"))]);
+        assert_eq!(value.to_compact(), "{\"k\\\"\":\"C:\\\\X\"}");
+    }
+
+    #[test]
+    fn scalars() {
+        let value = Value::Array(vec![
+            Value::Null,
+            Value::Bool(true),
+            Value::Int(-3),
+            Value::Float(42.5),
+            Value::Float(f64::NAN),
+            Value::Float(f64::INFINITY),
+        ]);
+        assert_eq!(value.to_compact(), "[null,true,-3,42.5,null,null]");
+    }
+
+    #[test]
+    fn compact_and_pretty() {
+        let mut value = Value::object(vec![
+            ("name", Value::string("A.TXT")),
+            ("tags", Value::Array(vec![])),
+        ]);
+        value.extend(vec![("size", Value::Int(56))]);
+        assert_eq!(
+            value.to_compact(),
+            "{\"name\":\"A.TXT\",\"tags\":[],\"size\":56}"
+        );
+        let nested = Value::object(vec![
+            ("files", Value::Array(vec![value])),
+            ("empty", Value::object(vec![])),
+        ]);
+        assert_eq!(
+            nested.to_pretty(),
+            "{\n  \"files\": [\n    {\n      \"name\": \"A.TXT\",\n      \
+             \"tags\": [],\n      \"size\": 56\n    }\n  ],\n  \
+             \"empty\": {}\n}"
+        );
+        let mut not_object = Value::Int(1);
+        not_object.extend(vec![("x", Value::Null)]);
+        assert_eq!(not_object.to_compact(), "1");
+    }
+}
@@ -0,0 +1,356 @@
+// UnQuantum - UNPAQ/PAQ-compatible front-ends
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Installed (or linked) under the name `unpaq` or `paq`, the binary accepts
+// the DOS command-line syntax old batch files use:
+//
+//   UNPAQ [/switches] archive[.Q] [destination\] [files...]
+//
+// Switches start with `/` or `-`, ignore case and can be grouped (`/D/O`,
+// `-do`). The arguments are translated into the equivalent unquantum
+// command line, so behaviour and exit codes are unquantum's; exit codes are
+// 0 on success and non-zero on failure, as `IF ERRORLEVEL 1` expects.
+//
+// Started as `unpaq`, listings and the per-file lines of extraction and
+// testing take the DOS layout of the time instead of unquantum's own:
+//
+//    Length    Date   Time   Name
+//   --------  -------- -----  ------------
+//         56  02-05-26 13:17  TEST1.TXT
+//   --------                  ------------
+//         56                  1 file(s)
+//
+//   Extracting: TEST1.TXT
+//   Testing:    TEST1.TXT  OK
+//
+// No copy of UNPAQ.EXE's screen output was available to check this
+// against, so it follows what DOS extractors of the time had in common
+// (sizes right-aligned, MM-DD-YY dates, one line per file) rather than
+// reproducing UNPAQ.EXE byte for byte. Banners, summaries and error
+// messages are unquantum's. PAQ, the compressor, is not implemented.
+
+use crate::QFileEntry;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const UNPAQ_USAGE: &str = r#"UNPAQ - Quantum archive extractor (UnQuantum compatibility mode)
+
+Usage: UNPAQ [/switches] archive[.Q] [destination\] [files...]
+
+  /E, /X   Extract files (default)
+  /L, /V   List archive contents
+  /T       Test archive integrity
+  /D       Restore directories stored in the archive
+  /O       Overwrite existing files (default)
+  /S       Skip existing files
+  /Q       Quiet: print only errors
+  /?, /H   Show this help
+
+The destination must end with \ or / or be an existing directory.
+Files may use the wildcards * and ?. Run 'unquantum --help' for all
+options of the full command line."#;
+
+const PAQ_USAGE: &str = r#"PAQ - Quantum archive compressor (UnQuantum compatibility mode)
+
+Creating Quantum archives is not supported: UnQuantum only reads them.
+Use the original PAQ.EXE, for example under DOSBox, or 'unpaq' to
+extract."#;
+
+/// Which front-end the binary was started as, from its file name
+/// (`unpaq`, `UNPAQ.EXE`, ...)
+fn invoked_as(program: &str) -> Option<&'static str> {
+    let stem = Path::new(program).file_stem()?.to_str()?;
+    ["unpaq", "paq"]
+        .into_iter()
+        .find(|name| stem.eq_ignore_ascii_case(name))
+}
+
+/// Whether the binary was started as `unpaq`, and so prints UNPAQ-style
+/// listings and progress lines
+pub fn is_unpaq(program: &str) -> bool {
+    invoked_as(program) == Some("unpaq")
+}
+
+/// Translate the command line when started as `unpaq` or `paq`; any other
+/// command line is returned unchanged
+pub fn translate(args: Vec<String>) -> Result<Vec<String>, String> {
+    match args.first().and_then(|program| invoked_as(program)) {
+        Some("unpaq") => unpaq_args(&args),
+        Some(_) => paq_args(&args),
+        None => Ok(args),
+    }
+}
+
+fn paq_args(args: &[String]) -> Result<Vec<String>, String> {
+    if args.len() < 2 || args[1..].iter().any(|a| a == "/?" || a == "-?") {
+        eprintln!("{}", PAQ_USAGE);
+        process::exit(0);
+    }
+    Err("PAQ (creating archives) is not supported; use unpaq to extract"
+        .to_string())
+}
+
+fn unpaq_args(args: &[String]) -> Result<Vec<String>, String> {
+    if args.len() < 2 {
+        eprintln!("{}", UNPAQ_USAGE);
+        process::exit(2);
+    }
+
+    let mut options = vec![args[0].clone()];
+    let mut operands = Vec::new();
+    for arg in &args[1..] {
+        match switches(arg) {
+            Some(letters) => {
+                for letter in letters {
+                    let option = match letter.to_ascii_uppercase() {
+                        'E' | 'X' => "--extract",
+                        'L' | 'V' => "--list",
+                        'T' => "--test",
+                        'D' => "--dirs",
+                        'O' => "--overwrite",
+                        'S' => "--skip-existing",
+                        'Q' => "--quiet",
+                        '?' | 'H' => {
+                            eprintln!("{}", UNPAQ_USAGE);
+                            process::exit(0);
+                        }
+                        _ => return Err(format!("Unknown switch /{}", letter)),
+                    };
+                    options.push(option.to_string());
+                }
+            }
+            None => operands.push(arg.clone()),
+        }
+    }
+
+    let mut operands = operands.into_iter();
+    let archive = operands.next().ok_or("No archive specified")?;
+    let mut patterns = Vec::new();
+    for operand in operands {
+        let is_dir =
+            operand.ends_with(['\\', '/']) || Path::new(&operand).is_dir();
+        if is_dir && !options.contains(&"--output".to_string()) {
+            options.extend(["--output".to_string(), dos_dir(&operand)]);
+        } else {
+            patterns.push(operand);
+        }
+    }
+
+    options.push("--".to_string());
+    options.push(find_archive(archive));
+    options.extend(patterns);
+    Ok(options)
+}
+
+/// The switch letters of a DOS switch group (`/D`, `/D/O`, `-do`), or None
+/// for an operand. A `/` argument is a switch group only if every part is a
+/// single letter, so absolute paths such as `/tmp/GAME.Q` stay operands.
+fn switches(arg: &str) -> Option<Vec<char>> {
+    if let Some(rest) = arg.strip_prefix('/') {
+        let parts: Vec<&str> = rest.split('/').collect();
+        if !parts.iter().all(|p| p.chars().count() == 1) {
+            return None;
+        }
+        return Some(parts.iter().filter_map(|p| p.chars().next()).collect());
+    }
+    match arg.strip_prefix('-') {
+        Some(letters) if !letters.is_empty() => Some(letters.chars().collect()),
+        _ => None,
+    }
+}
+
+/// A DOS path as the local system writes it: outside Windows, `\` becomes
+/// `/`
+fn local_path(path: &str) -> String {
+    if cfg!(windows) {
+        path.to_string()
+    } else {
+        path.replace('\\', "/")
+    }
+}
+
+/// A destination directory without its trailing separator
+fn dos_dir(dir: &str) -> String {
+    let dir = local_path(dir);
+    match dir.trim_end_matches(['\\', '/']) {
+        "" => dir[..1].to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// The archive a DOS command line means: `.Q` is added to a name without an
+/// extension, and names match files on disk regardless of case, as on DOS
+fn find_archive(archive: String) -> String {
+    let archive = local_path(&archive);
+    let path = Path::new(&archive);
+    if archive == "-" || path.exists() {
+        return archive;
+    }
+    let name = match path.extension() {
+        Some(_) => archive.clone(),
+        None => format!("{}.Q", archive),
+    };
+    let path = Path::new(&name);
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name())
+    else {
+        return name;
+    };
+    let dir = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let found = fs::read_dir(dir).ok().and_then(|entries| {
+        entries.filter_map(|entry| entry.ok()).find(|entry| {
+            entry.file_name().eq_ignore_ascii_case(file_name)
+                && entry.path().is_file()
+        })
+    });
+    match found {
+        Some(entry) => {
+            parent.join(entry.file_name()).to_string_lossy().into_owned()
+        }
+        None => name,
+    }
+}
+
+/// The listing of the entries at `order`, in the DOS layout
+pub fn listing(files: &[QFileEntry], order: &[usize]) -> Vec<String> {
+    let mut lines = vec![
+        " Length    Date   Time   Name".to_string(),
+        "--------  -------- -----  ------------".to_string(),
+    ];
+    let mut total: u64 = 0;
+    for &index in order {
+        let f = &files[index];
+        let t = f.datetime();
+        lines.push(format!(
+            "{:>8}  {:02}-{:02}-{:02} {:02}:{:02}  {}",
+            f.size,
+            t.month,
+            t.day,
+            t.year % 100,
+            t.hour,
+            t.minute,
+            f.name
+        ));
+        total += f.size as u64;
+    }
+    lines.push("--------                  ------------".to_string());
+    let count = format!("{} file(s)", order.len());
+    lines.push(format!("{:>8}                  {}", total, count));
+    lines
+}
+
+/// The line shown for an entry as it is extracted (`verb` "Extracting") or
+/// tested ("Testing")
+pub fn entry_line(verb: &str, name: &str) -> String {
+    format!("{:<12}{}", format!("{}:", verb), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpaq(args: &[&str]) -> Result<Vec<String>, String> {
+        let mut all = vec!["UNPAQ.EXE".to_string()];
+        all.extend(args.iter().map(|a| a.to_string()));
+        translate(all)
+    }
+
+    #[test]
+    fn front_end_by_program_name() {
+        assert_eq!(invoked_as("/usr/bin/unpaq"), Some("unpaq"));
+        assert_eq!(invoked_as("UNPAQ.EXE"), Some("unpaq"));
+        assert_eq!(invoked_as("paq"), Some("paq"));
+        assert_eq!(invoked_as("unquantum"), None);
+        assert_eq!(invoked_as("unpaqx"), None);
+        let args = vec!["unquantum".to_string(), "/L".to_string()];
+        assert_eq!(translate(args.clone()), Ok(args));
+    }
+
+    #[test]
+    fn switch_groups() {
+        assert_eq!(switches("/D"), Some(vec!['D']));
+        assert_eq!(switches("/d/O"), Some(vec!['d', 'O']));
+        assert_eq!(switches("-do"), Some(vec!['d', 'o']));
+        assert_eq!(switches("/tmp/GAME.Q"), None);
+        assert_eq!(switches("/DO"), None);
+        assert_eq!(switches("-"), None);
+        assert_eq!(switches("GAME"), None);
+    }
+
+    #[test]
+    fn command_lines() {
+        let missing = "no-such-dir-for-unpaq-tests";
+        assert_eq!(
+            unpaq(&["/T", &format!("{}/GAME.Q", missing)]).unwrap(),
+            [
+                "UNPAQ.EXE",
+                "--test",
+                "--",
+                &format!("{}/GAME.Q", missing)
+            ]
+        );
+        let out = unpaq(&["/d/s", "game", "OUT\\", "*.TXT"]).unwrap();
+        assert_eq!(
+            out,
+            [
+                "UNPAQ.EXE",
+                "--dirs",
+                "--skip-existing",
+                "--output",
+                &local_path("OUT"),
+                "--",
+                "game.Q",
+                "*.TXT"
+            ]
+        );
+        assert!(unpaq(&["/Z", "GAME.Q"]).is_err());
+        assert!(unpaq(&["/D"]).is_err());
+    }
+
+    #[test]
+    fn dos_layout() {
+        let entry = |name: &str, size, date, time| QFileEntry {
+            name: name.to_string(),
+            comment: String::new(),
+            name_raw: name.as_bytes().to_vec(),
+            comment_raw: Vec::new(),
+            size,
+            time,
+            date,
+        };
+        // 1994-06-15 13:45:20 and 2026-02-05 13:17:58
+        let files = [
+            entry("README.TXT", 1234, 0x1CCF, 0x6DAA),
+            entry("DOCS\\GAME.DOC", 56, 0x5C45, 0x6A3D),
+        ];
+        assert_eq!(
+            listing(&files, &[1, 0]),
+            [
+                " Length    Date   Time   Name",
+                "--------  -------- -----  ------------",
+                "      56  02-05-26 13:17  DOCS\\GAME.DOC",
+                "    1234  06-15-94 13:45  README.TXT",
+                "--------                  ------------",
+                "    1290                  2 file(s)",
+            ]
+        );
+        assert_eq!(entry_line("Extracting", "A.TXT"), "Extracting: A.TXT");
+        assert_eq!(entry_line("Testing", "A.TXT"), "Testing:    A.TXT");
+        assert!(is_unpaq("UNPAQ.EXE"));
+        assert!(!is_unpaq("paq"));
+    }
+
+    #[test]
+    fn dos_paths() {
+        assert_eq!(dos_dir("GAMES\\"), local_path("GAMES"));
+        assert_eq!(dos_dir("\\"), local_path("\\"));
+        assert_eq!(dos_dir("out/"), "out");
+        assert_eq!(find_archive("-".to_string()), "-");
+    }
+}
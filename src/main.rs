@@ -16,11 +16,34 @@
 //
 // This tool handles standalone .Q archive files (not CAB-embedded Quantum).
 
+#[macro_use]
+mod output;
+
+mod codepage;
+mod compare;
+mod dostime;
+mod fat;
+mod hash;
+mod json;
+mod legacy;
+mod paths;
+mod progress;
+mod report;
+mod select;
+#[cfg(not(windows))]
+mod tzfile;
+
+use codepage::CodePage;
+use dostime::{DateFormat, DosDateTime, TimeZone};
 use std::env;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{BufRead, ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::SystemTime;
 
 // ============================================================================
 // Constants - Quantum static data tables
@@ -29,6 +52,15 @@ use std::process;
 /// Magic signature for Quantum archives: 0x44 0x53 ("DS" - David Stafford)
 const QTM_SIGNATURE: [u8; 2] = [0x44, 0x53];
 
+/// Archive format version written by PAQ.EXE v0.97, the reference version
+/// for this decoder
+const QTM_VERSION_MAJOR: u8 = 0;
+const QTM_VERSION_MINOR: u8 = 97;
+
+/// Compression flags: bits 0-2 hold the encoder's compression level (1-7).
+/// The level only steers the match finder and does not change decoding.
+const COMP_FLAG_LEVEL_MASK: u8 = 0x07;
+
 /// Position slot base offsets (42 entries)
 /// Maps position slot numbers to base match offsets.
 const POSITION_BASE: [u32; 42] = [
@@ -57,6 +89,158 @@ const LENGTH_EXTRA: [u8; 27] = [
     5, 5, 5, 0,
 ];
 
+// ============================================================================
+// Decoder profiles
+// ============================================================================
+
+/// Model parameters that can differ between Quantum releases.
+///
+/// PAQ.EXE v0.97 and the Microsoft CAB decoder agree on every value. Earlier
+/// PAQ betas are not documented, so a profile can also be assembled by hand
+/// with `--profile base:key=value,...` when probing such archives.
+#[derive(Clone, Copy, PartialEq)]
+struct QuantumProfile {
+    name: &'static str,
+    /// Rescale a model when its total frequency exceeds this value
+    rescale_threshold: u16,
+    /// Frequency added to a symbol (and those above it) when decoded
+    freq_increment: u16,
+    /// Halving rescales before the first full rebuild of a model
+    initial_shift: i32,
+    /// Halving rescales between later full rebuilds
+    shift_reset: i32,
+    /// Position slot caps for the 3-byte and 4-byte match models
+    model4_cap: usize,
+    model5_cap: usize,
+    /// Length added to the decoded value of selector 6 matches
+    selector6_min_length: usize,
+}
+
+/// PAQ.EXE / UNPAQ.EXE v0.97, the only documented version. Quantum as
+/// embedded in Microsoft Cabinet files uses the same model.
+const PROFILE_Q097: QuantumProfile = QuantumProfile {
+    name: "0.97",
+    rescale_threshold: 3800,
+    freq_increment: 8,
+    initial_shift: 4,
+    shift_reset: 50,
+    model4_cap: 24,
+    model5_cap: 36,
+    selector6_min_length: 5,
+};
+
+/// Profiles by the name --profile accepts
+const PROFILES: [(&str, QuantumProfile); 2] =
+    [("0.97", PROFILE_Q097), ("cab", PROFILE_Q097)];
+
+/// The profile for each archive version this decoder knows. Every PAQ.EXE
+/// archive seen so far says 0.97; the betas that wrote other versions are
+/// undocumented and have no entry.
+const VERSION_PROFILES: [((u8, u8), QuantumProfile); 1] =
+    [((QTM_VERSION_MAJOR, QTM_VERSION_MINOR), PROFILE_Q097)];
+
+impl QuantumProfile {
+    /// The profile recorded for an archive version, if the version is known
+    fn for_version(major: u8, minor: u8) -> Option<QuantumProfile> {
+        VERSION_PROFILES
+            .iter()
+            .find(|(version, _)| *version == (major, minor))
+            .map(|&(_, profile)| profile)
+    }
+
+    /// The profile an archive is decoded with: --profile when given,
+    /// otherwise the one for its version. Unknown versions (decoded only
+    /// with --force) fall back to 0.97, the only documented model.
+    fn select(
+        header: &QArchiveHeader,
+        chosen: Option<QuantumProfile>,
+    ) -> QuantumProfile {
+        chosen
+            .or_else(|| {
+                Self::for_version(header.major_version, header.minor_version)
+            })
+            .unwrap_or(PROFILE_Q097)
+    }
+
+    /// Parse a --profile value: a profile name, optionally followed by
+    /// parameter overrides, e.g. `0.97:threshold=4000,increment=4`
+    fn parse(spec: &str) -> Result<QuantumProfile, String> {
+        let (base, overrides) = match spec.split_once(':') {
+            Some((base, overrides)) => (base, Some(overrides)),
+            None => (spec, None),
+        };
+        let mut profile = PROFILES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(base))
+            .map(|&(_, profile)| profile)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PROFILES.iter().map(|p| p.0).collect();
+                format!(
+                    "Unknown profile '{}' (known: {})",
+                    base,
+                    names.join(", ")
+                )
+            })?;
+
+        let Some(overrides) = overrides else {
+            return Ok(profile);
+        };
+        profile.name = "custom";
+        for item in overrides.split(',').filter(|s| !s.is_empty()) {
+            let (key, value) = item.split_once('=').ok_or_else(|| {
+                format!("Profile override '{}' must be key=value", item)
+            })?;
+            let value: u32 = value.parse().map_err(|_| {
+                format!("Invalid number '{}' for profile key '{}'", value, key)
+            })?;
+            let in_range = |max: u32| -> Result<u32, String> {
+                if value == 0 || value > max {
+                    Err(format!(
+                        "Profile key '{}' must be between 1 and {}",
+                        key, max
+                    ))
+                } else {
+                    Ok(value)
+                }
+            };
+            match key {
+                "threshold" => profile.rescale_threshold = in_range(60000)? as u16,
+                "increment" => profile.freq_increment = in_range(256)? as u16,
+                "shift" => profile.initial_shift = in_range(1000)? as i32,
+                "reset" => profile.shift_reset = in_range(1000)? as i32,
+                "model4" => profile.model4_cap = in_range(42)? as usize,
+                "model5" => profile.model5_cap = in_range(42)? as usize,
+                "minlen" => {
+                    profile.selector6_min_length = in_range(256)? as usize
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown profile key '{}' (known: threshold, increment, \
+                         shift, reset, model4, model5, minlen)",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(profile)
+    }
+
+    /// One-line description of the parameters, for -i
+    fn describe(&self) -> String {
+        format!(
+            "{} (threshold {}, increment {}, shift {}/{}, caps {}/{}, min length {})",
+            self.name,
+            self.rescale_threshold,
+            self.freq_increment,
+            self.initial_shift,
+            self.shift_reset,
+            self.model4_cap,
+            self.model5_cap,
+            self.selector6_min_length
+        )
+    }
+}
+
 // ============================================================================
 // Archive structures
 // ============================================================================
@@ -70,22 +254,89 @@ struct QArchiveHeader {
     comp_flags: u8,
 }
 
-/// A file entry within the Quantum archive
+impl QArchiveHeader {
+    /// Compression level recorded by the encoder
+    fn compression_level(&self) -> u8 {
+        self.comp_flags & COMP_FLAG_LEVEL_MASK
+    }
+
+    /// Compression flag bits with no known meaning
+    fn unknown_flags(&self) -> u8 {
+        self.comp_flags & !COMP_FLAG_LEVEL_MASK
+    }
+
+    /// Describe the compression flags as named options
+    fn flag_names(&self) -> Vec<String> {
+        let mut names = vec![format!("level {}", self.compression_level())];
+        for bit in 3..8 {
+            if self.comp_flags & (1 << bit) != 0 {
+                names.push(format!("unknown bit {}", bit));
+            }
+        }
+        names
+    }
+
+    /// Check the version and flags against what this decoder understands.
+    /// Returns warnings for headers that are probably decodable, or an error
+    /// for headers that are not.
+    fn compatibility(&self) -> Result<Vec<String>, String> {
+        // Versions without a profile, such as the undocumented PAQ betas,
+        // are refused; --force decodes them anyway (see QuantumProfile::select)
+        if QuantumProfile::for_version(self.major_version, self.minor_version)
+            .is_none()
+        {
+            return Err(format!(
+                "Unsupported Quantum version {}.{:02} (only 0.97 is known)",
+                self.major_version, self.minor_version
+            ));
+        }
+
+        let mut warnings = Vec::new();
+        if self.compression_level() == 0 {
+            warnings.push(
+                "Compression level 0 is not written by PAQ.EXE".to_string(),
+            );
+        }
+        if self.unknown_flags() != 0 {
+            warnings.push(format!(
+                "Unknown compression flag bits 0x{:02X}, output may be wrong",
+                self.unknown_flags()
+            ));
+        }
+        Ok(warnings)
+    }
+}
+
+/// A file entry within the Quantum archive.
+/// Names and comments are kept as stored, and decoded with a DOS code page.
 struct QFileEntry {
     name: String,
     comment: String,
+    name_raw: Vec<u8>,
+    comment_raw: Vec<u8>,
     size: u32,
     time: u16,
     date: u16,
 }
 
 impl QFileEntry {
+    /// The archived modification time
+    fn datetime(&self) -> DosDateTime {
+        DosDateTime::from_dos(self.date, self.time)
+    }
+
     /// Format the DOS date as a human-readable string
-    fn date_string(&self) -> String {
+    fn date_string(&self, format: DateFormat) -> String {
         let day = self.date & 0x1F;
         let month = (self.date >> 5) & 0x0F;
         let year = ((self.date >> 9) & 0x7F) + 1980;
-        format!("{:02}-{:02}-{:04}", day, month, year)
+        format.format(year, month as u8, day as u8)
+    }
+
+    /// Directory levels above the file: 0 for `README.TXT`, 1 for
+    /// `DOCS\README.TXT`
+    fn depth(&self) -> usize {
+        self.name.matches(['\\', '/']).count()
     }
 
     /// Format the DOS time as a human-readable string
@@ -109,13 +360,16 @@ struct ModelSym {
 
 struct Model {
     shift_left: i32,
+    shift_reset: i32,
+    increment: u16,
+    threshold: u16,
     entries: usize,
     syms: Vec<ModelSym>,
 }
 
 impl Model {
     /// Create a new adaptive frequency model for symbols [start..start+len)
-    fn new(start: u16, len: usize) -> Self {
+    fn new(start: u16, len: usize, profile: &QuantumProfile) -> Self {
         let mut syms = Vec::with_capacity(len + 1);
         for i in 0..=len {
             syms.push(ModelSym {
@@ -124,13 +378,16 @@ impl Model {
             });
         }
         Model {
-            shift_left: 4,
+            shift_left: profile.initial_shift,
+            shift_reset: profile.shift_reset,
+            increment: profile.freq_increment,
+            threshold: profile.rescale_threshold,
             entries: len,
             syms,
         }
     }
 
-    /// Rescale model frequencies when cumfreq exceeds 3800
+    /// Rescale model frequencies when cumfreq exceeds the threshold
     fn update(&mut self) {
         self.shift_left -= 1;
         if self.shift_left > 0 {
@@ -142,7 +399,7 @@ impl Model {
                 }
             }
         } else {
-            self.shift_left = 50;
+            self.shift_left = self.shift_reset;
             // Convert cumulative frequencies to individual frequencies
             for i in 0..self.entries {
                 self.syms[i].cumfreq -= self.syms[i + 1].cumfreq;
@@ -188,20 +445,10 @@ impl BitReader {
 
     /// Read 2 bytes in big-endian order and inject 16 bits into the buffer
     fn fill(&mut self) {
-        let b0 = if self.pos < self.data.len() {
-            let b = self.data[self.pos];
-            self.pos += 1;
-            b
-        } else {
-            0 // pad with zeros at end of input
-        };
-        let b1 = if self.pos < self.data.len() {
-            let b = self.data[self.pos];
-            self.pos += 1;
-            b
-        } else {
-            0
-        };
+        // Pad with zeros at end of input; pos keeps counting the padding
+        let b0 = self.data.get(self.pos).copied().unwrap_or(0);
+        let b1 = self.data.get(self.pos + 1).copied().unwrap_or(0);
+        self.pos += 2;
         let word = ((b0 as u32) << 8) | (b1 as u32);
         // MSB inject: place new bits after existing valid bits
         // bit_buffer has valid bits at positions [31..(32-bits_left)]
@@ -210,6 +457,20 @@ impl BitReader {
         self.bits_left += 16;
     }
 
+    /// Whether the decoder has needed more input than there is, which means
+    /// the data is truncated or damaged. Complete streams end with the
+    /// coder's read-ahead still inside the data.
+    fn overran(&self) -> bool {
+        self.pos > self.data.len()
+    }
+
+    /// Bits taken from the input so far, not counting those still buffered
+    /// or the zero padding read past its end
+    fn bits_consumed(&self) -> u64 {
+        (self.pos as u64 * 8 - self.bits_left as u64)
+            .min(self.data.len() as u64 * 8)
+    }
+
     fn ensure_bits(&mut self, n: i32) {
         while self.bits_left < n {
             self.fill();
@@ -315,7 +576,7 @@ fn decode_symbol(
         let mut j = i;
         loop {
             j -= 1;
-            model.syms[j].cumfreq += 8;
+            model.syms[j].cumfreq += model.increment;
             if j == 0 {
                 break;
             }
@@ -323,7 +584,7 @@ fn decode_symbol(
     }
 
     // Rescale if total frequency exceeds threshold
-    if model.syms[0].cumfreq > 3800 {
+    if model.syms[0].cumfreq > model.threshold {
         model.update();
     }
 
@@ -350,74 +611,123 @@ fn decode_symbol(
     Ok(sym)
 }
 
-/// Decompress a Quantum compressed data stream.
+/// Bytes decoded between calls of a decoder's progress hook
+const PROGRESS_STEP: usize = 64 * 1024;
+
+/// Incremental Quantum decoder.
 ///
 /// The standalone .Q format compresses all files as a single continuous stream.
 /// The arithmetic coder state and adaptive models persist across file boundaries.
 /// Between each file (except after the last), a 16-bit checksum is embedded in
 /// the raw bit stream that must be consumed to keep the decoder in sync.
-fn quantum_decompress(
-    compressed_data: Vec<u8>,
-    file_sizes: &[u32],
-    window_bits: u8,
-) -> Result<Vec<u8>, String> {
-    let total_output_size: usize = file_sizes.iter().map(|&s| s as usize).sum();
-    let window_size = 1usize << window_bits;
-    let mut window = vec![0u8; window_size];
-    let mut window_posn: usize = 0;
-    let mut output = Vec::with_capacity(total_output_size);
-
-    let mut bits = BitReader::new(compressed_data);
-
-    // Initialize adaptive frequency models
-    let i = (window_bits as usize) * 2;
-    let mut model0 = Model::new(0, 64);
-    let mut model1 = Model::new(64, 64);
-    let mut model2 = Model::new(128, 64);
-    let mut model3 = Model::new(192, 64);
-    let mut model4 = Model::new(0, if i > 24 { 24 } else { i });
-    let mut model5 = Model::new(0, if i > 36 { 36 } else { i });
-    let mut model6 = Model::new(0, i);
-    let mut model6len = Model::new(0, 27);
-    let mut model7 = Model::new(0, 7);
-
-    // Initialize arithmetic coder
-    let mut h: u16 = 0xFFFF;
-    let mut l: u16 = 0;
-    let mut c: u16 = bits.read_bits(16) as u16;
-
-    // Decompress each file, consuming the inter-file checksum between them
-    for (file_idx, &file_size) in file_sizes.iter().enumerate() {
-        let file_end = output.len() + file_size as usize;
+///
+/// Files are decoded one at a time with `decode_file`, followed by
+/// `read_checksum` between files, so callers can act on each file as soon as
+/// it is complete.
+struct QuantumDecoder {
+    bits: BitReader,
+    window: Vec<u8>,
+    window_posn: usize,
+    model0: Model,
+    model1: Model,
+    model2: Model,
+    model3: Model,
+    model4: Model,
+    model5: Model,
+    model6: Model,
+    model6len: Model,
+    model7: Model,
+    selector6_min_length: usize,
+    h: u16,
+    l: u16,
+    c: u16,
+}
+
+impl QuantumDecoder {
+    fn new(
+        compressed_data: Vec<u8>,
+        window_bits: u8,
+        profile: &QuantumProfile,
+    ) -> Self {
+        let mut bits = BitReader::new(compressed_data);
+
+        // Initialize adaptive frequency models
+        let i = (window_bits as usize) * 2;
+
+        // Initialize arithmetic coder
+        let c = bits.read_bits(16) as u16;
+
+        QuantumDecoder {
+            bits,
+            window: vec![0u8; 1usize << window_bits],
+            window_posn: 0,
+            model0: Model::new(0, 64, profile),
+            model1: Model::new(64, 64, profile),
+            model2: Model::new(128, 64, profile),
+            model3: Model::new(192, 64, profile),
+            model4: Model::new(0, i.min(profile.model4_cap), profile),
+            model5: Model::new(0, i.min(profile.model5_cap), profile),
+            model6: Model::new(0, i, profile),
+            model6len: Model::new(0, 27, profile),
+            model7: Model::new(0, 7, profile),
+            selector6_min_length: profile.selector6_min_length,
+            h: 0xFFFF,
+            l: 0,
+            c,
+        }
+    }
+
+    /// Decode the next file of `file_size` bytes, appending it to `output`.
+    ///
+    /// `progress`, if given, is called with the number of bytes of this file
+    /// decoded so far, every PROGRESS_STEP bytes. That is also when running
+    /// out of input is checked for, so a damaged header's huge size does not
+    /// decode gigabytes of padding; the loop pays for one comparison per
+    /// symbol.
+    fn decode_file(
+        &mut self,
+        file_size: usize,
+        output: &mut Vec<u8>,
+        mut progress: Option<&mut dyn FnMut(usize)>,
+    ) -> Result<(), String> {
+        let window_size = self.window.len();
+        let bits = &mut self.bits;
+        let (h, l, c) = (&mut self.h, &mut self.l, &mut self.c);
+        let file_start = output.len();
+        let file_end = file_start + file_size;
+        let mut next_report = file_start + PROGRESS_STEP;
 
         while output.len() < file_end {
-            let selector =
-                decode_symbol(&mut model7, &mut bits, &mut h, &mut l, &mut c)?;
+            if output.len() >= next_report {
+                if bits.overran() {
+                    return Err("Compressed data ends early".to_string());
+                }
+                if let Some(report) = progress.as_mut() {
+                    report(output.len() - file_start);
+                }
+                next_report = output.len() + PROGRESS_STEP;
+            }
+            let selector = decode_symbol(&mut self.model7, bits, h, l, c)?;
 
             if selector < 4 {
                 let model = match selector {
-                    0 => &mut model0,
-                    1 => &mut model1,
-                    2 => &mut model2,
-                    3 => &mut model3,
+                    0 => &mut self.model0,
+                    1 => &mut self.model1,
+                    2 => &mut self.model2,
+                    3 => &mut self.model3,
                     _ => unreachable!(),
                 };
-                let sym =
-                    decode_symbol(model, &mut bits, &mut h, &mut l, &mut c)?;
+                let sym = decode_symbol(model, bits, h, l, c)?;
                 let byte = sym as u8;
-                window[window_posn] = byte;
-                window_posn = (window_posn + 1) & (window_size - 1);
+                self.window[self.window_posn] = byte;
+                self.window_posn = (self.window_posn + 1) & (window_size - 1);
                 output.push(byte);
             } else {
                 let (match_offset, match_length) = match selector {
                     4 => {
-                        let sym = decode_symbol(
-                            &mut model4,
-                            &mut bits,
-                            &mut h,
-                            &mut l,
-                            &mut c,
-                        )? as usize;
+                        let sym =
+                            decode_symbol(&mut self.model4, bits, h, l, c)?
+                                as usize;
                         if sym >= 42 {
                             return Err(format!(
                                 "Invalid position slot {} in selector 4",
@@ -431,13 +741,9 @@ fn quantum_decompress(
                         (offset, 3usize)
                     }
                     5 => {
-                        let sym = decode_symbol(
-                            &mut model5,
-                            &mut bits,
-                            &mut h,
-                            &mut l,
-                            &mut c,
-                        )? as usize;
+                        let sym =
+                            decode_symbol(&mut self.model5, bits, h, l, c)?
+                                as usize;
                         if sym >= 42 {
                             return Err(format!(
                                 "Invalid position slot {} in selector 5",
@@ -451,13 +757,9 @@ fn quantum_decompress(
                         (offset, 4usize)
                     }
                     6 => {
-                        let len_sym = decode_symbol(
-                            &mut model6len,
-                            &mut bits,
-                            &mut h,
-                            &mut l,
-                            &mut c,
-                        )? as usize;
+                        let len_sym =
+                            decode_symbol(&mut self.model6len, bits, h, l, c)?
+                                as usize;
                         if len_sym >= 27 {
                             return Err(format!(
                                 "Invalid length slot {}",
@@ -468,15 +770,11 @@ fn quantum_decompress(
                             bits.read_many_bits(LENGTH_EXTRA[len_sym] as i32);
                         let length = LENGTH_BASE[len_sym] as usize
                             + len_extra as usize
-                            + 5;
-
-                        let pos_sym = decode_symbol(
-                            &mut model6,
-                            &mut bits,
-                            &mut h,
-                            &mut l,
-                            &mut c,
-                        )? as usize;
+                            + self.selector6_min_length;
+
+                        let pos_sym =
+                            decode_symbol(&mut self.model6, bits, h, l, c)?
+                                as usize;
                         if pos_sym >= 42 {
                             return Err(format!(
                                 "Invalid position slot {} in selector 6",
@@ -497,125 +795,159 @@ fn quantum_decompress(
                     }
                 };
 
-                let mut src = (window_posn + window_size - match_offset)
+                let mut src = (self.window_posn + window_size - match_offset)
                     & (window_size - 1);
                 let bytes_to_copy =
                     match_length.min(file_end - output.len());
 
                 for _ in 0..bytes_to_copy {
-                    let byte = window[src];
-                    window[window_posn] = byte;
+                    let byte = self.window[src];
+                    self.window[self.window_posn] = byte;
                     output.push(byte);
                     src = (src + 1) & (window_size - 1);
-                    window_posn = (window_posn + 1) & (window_size - 1);
+                    self.window_posn =
+                        (self.window_posn + 1) & (window_size - 1);
                 }
             }
         }
+        Ok(())
+    }
 
-        // Between files: consume the 16-bit checksum from the raw bit stream.
-        // The coder state (H, L, C) and models are preserved across files.
-        if file_idx < file_sizes.len() - 1 {
-            let _checksum = bits.read_bits(16);
-        }
+    /// Between files: consume the 16-bit checksum from the raw bit stream.
+    /// The coder state (H, L, C) and models are preserved across files.
+    fn read_checksum(&mut self) -> u16 {
+        self.bits.read_bits(16) as u16
+    }
+
+    /// Whether decoding has run past the end of the compressed data
+    fn overran(&self) -> bool {
+        self.bits.overran()
     }
 
-    Ok(output)
+    /// Compressed bits read so far. The difference across a `decode_file`
+    /// call estimates the file's compressed size: the arithmetic coder reads
+    /// ahead, so bits near a file boundary may be counted in either file.
+    fn bits_consumed(&self) -> u64 {
+        self.bits.bits_consumed()
+    }
 }
 
 // ============================================================================
 // Archive parsing
 // ============================================================================
 
-/// Read a variable-length string prefix.
-/// If length < 128, stored as one byte.
-/// If >= 128, high bit set and remaining 15 bits contain the length (big-endian).
-fn read_var_length(data: &[u8], pos: &mut usize) -> Result<usize, String> {
-    if *pos >= data.len() {
-        return Err(
-            "Unexpected end of archive reading string length".to_string(),
-        );
+/// Reads header fields from the front of an archive in a single forward
+/// pass, so that archives can come from a pipe as well as from memory
+struct HeaderReader<'a, R: Read> {
+    input: &'a mut R,
+    /// Bytes consumed so far
+    pos: usize,
+}
+
+impl<R: Read> HeaderReader<'_, R> {
+    fn read_bytes(&mut self, len: usize, what: &str) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0u8; len];
+        self.input.read_exact(&mut buf).map_err(|e| {
+            if e.kind() == ErrorKind::UnexpectedEof {
+                format!("Unexpected end of archive reading {}", what)
+            } else {
+                format!("Cannot read archive: {}", e)
+            }
+        })?;
+        self.pos += len;
+        Ok(buf)
     }
-    let first = data[*pos];
-    *pos += 1;
-    if first < 128 {
-        Ok(first as usize)
-    } else {
-        if *pos >= data.len() {
-            return Err(
-                "Unexpected end of archive reading string length".to_string(),
-            );
-        }
-        let second = data[*pos];
-        *pos += 1;
-        let len = (((first & 0x7F) as usize) << 8) | (second as usize);
-        Ok(len)
+
+    fn read_u8(&mut self, what: &str) -> Result<u8, String> {
+        Ok(self.read_bytes(1, what)?[0])
     }
-}
 
-/// Read a variable-length string from the archive
-fn read_var_string(data: &[u8], pos: &mut usize) -> Result<String, String> {
-    let len = read_var_length(data, pos)?;
-    if *pos + len > data.len() {
-        return Err(format!(
-            "String length {} exceeds available data at offset {}",
-            len, *pos
-        ));
+    /// Read a little-endian u16
+    fn read_u16_le(&mut self) -> Result<u16, String> {
+        let b = self.read_bytes(2, "u16")?;
+        Ok((b[0] as u16) | ((b[1] as u16) << 8))
     }
-    let s = String::from_utf8_lossy(&data[*pos..*pos + len]).to_string();
-    *pos += len;
-    Ok(s)
-}
 
-/// Read a little-endian u16 from the data
-fn read_u16_le(data: &[u8], pos: &mut usize) -> Result<u16, String> {
-    if *pos + 2 > data.len() {
-        return Err("Unexpected end of archive reading u16".to_string());
+    /// Read a little-endian u32
+    fn read_u32_le(&mut self) -> Result<u32, String> {
+        let b = self.read_bytes(4, "u32")?;
+        Ok((b[0] as u32)
+            | ((b[1] as u32) << 8)
+            | ((b[2] as u32) << 16)
+            | ((b[3] as u32) << 24))
+    }
+
+    /// Read a variable-length string prefix.
+    /// If length < 128, stored as one byte.
+    /// If >= 128, high bit set and remaining 15 bits contain the length
+    /// (big-endian).
+    fn read_var_length(&mut self) -> Result<usize, String> {
+        let first = self.read_u8("string length")?;
+        if first < 128 {
+            Ok(first as usize)
+        } else {
+            let second = self.read_u8("string length")?;
+            Ok((((first & 0x7F) as usize) << 8) | (second as usize))
+        }
     }
-    let val = (data[*pos] as u16) | ((data[*pos + 1] as u16) << 8);
-    *pos += 2;
-    Ok(val)
-}
 
-/// Read a little-endian u32 from the data
-fn read_u32_le(data: &[u8], pos: &mut usize) -> Result<u32, String> {
-    if *pos + 4 > data.len() {
-        return Err("Unexpected end of archive reading u32".to_string());
+    /// Read a variable-length string from the archive, as raw bytes
+    fn read_var_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.read_var_length()?;
+        let pos = self.pos;
+        self.read_bytes(len, "string").map_err(|_| {
+            format!(
+                "String length {} exceeds available data at offset {}",
+                len, pos
+            )
+        })
     }
-    let val = (data[*pos] as u32)
-        | ((data[*pos + 1] as u32) << 8)
-        | ((data[*pos + 2] as u32) << 16)
-        | ((data[*pos + 3] as u32) << 24);
-    *pos += 4;
-    Ok(val)
 }
 
-/// Parse a complete Quantum archive from raw data.
+/// Quick signature sniff: "DS" followed by a plausible header.
+/// Used to recognise archives that do not carry a .Q extension.
+fn looks_like_quantum(data: &[u8]) -> bool {
+    data.len() >= 8 && data[..2] == QTM_SIGNATURE && (10..=21).contains(&data[6])
+}
+
+/// Parse a complete Quantum archive from raw data, decoding names and
+/// comments with the given code page.
 /// Returns (header, file_entries, offset_to_compressed_data).
 fn parse_archive(
     data: &[u8],
+    codepage: CodePage,
 ) -> Result<(QArchiveHeader, Vec<QFileEntry>, usize), String> {
     if data.len() < 8 {
         return Err("File is too small to be a Quantum archive".to_string());
     }
+    read_archive_header(&mut &data[..], codepage)
+}
+
+/// Read the archive header and file entries from the front of a stream,
+/// leaving it positioned at the compressed data.
+/// Returns (header, file_entries, offset_to_compressed_data).
+fn read_archive_header(
+    input: &mut impl Read,
+    codepage: CodePage,
+) -> Result<(QArchiveHeader, Vec<QFileEntry>, usize), String> {
+    let mut reader = HeaderReader { input, pos: 0 };
 
     // Verify signature "DS" (0x44 0x53)
-    if data[0] != QTM_SIGNATURE[0] || data[1] != QTM_SIGNATURE[1] {
+    let signature = reader
+        .read_bytes(2, "signature")
+        .map_err(|_| "File is too small to be a Quantum archive".to_string())?;
+    if signature != QTM_SIGNATURE {
         return Err(format!(
             "Invalid signature: expected 0x{:02X}{:02X} ('DS'), got 0x{:02X}{:02X}",
-            QTM_SIGNATURE[0], QTM_SIGNATURE[1], data[0], data[1]
+            QTM_SIGNATURE[0], QTM_SIGNATURE[1], signature[0], signature[1]
         ));
     }
 
-    let mut pos = 2usize;
-    let major_version = data[pos];
-    pos += 1;
-    let minor_version = data[pos];
-    pos += 1;
-    let num_files = read_u16_le(data, &mut pos)?;
-    let table_size = data[pos];
-    pos += 1;
-    let comp_flags = data[pos];
-    pos += 1;
+    let major_version = reader.read_u8("header")?;
+    let minor_version = reader.read_u8("header")?;
+    let num_files = reader.read_u16_le()?;
+    let table_size = reader.read_u8("header")?;
+    let comp_flags = reader.read_u8("header")?;
 
     let header = QArchiveHeader {
         major_version,
@@ -636,26 +968,43 @@ fn parse_archive(
     // Parse file entries
     let mut files = Vec::with_capacity(num_files as usize);
     for file_idx in 0..num_files {
-        let name = read_var_string(data, &mut pos).map_err(|e| {
+        let name_raw = reader.read_var_bytes().map_err(|e| {
             format!("Error reading filename for file {}: {}", file_idx, e)
         })?;
-        let comment = read_var_string(data, &mut pos).map_err(|e| {
+        let comment_raw = reader.read_var_bytes().map_err(|e| {
             format!("Error reading comment for file {}: {}", file_idx, e)
         })?;
-        let size = read_u32_le(data, &mut pos)?;
-        let time = read_u16_le(data, &mut pos)?;
-        let date = read_u16_le(data, &mut pos)?;
+        let size = reader.read_u32_le()?;
+        let time = reader.read_u16_le()?;
+        let date = reader.read_u16_le()?;
 
         files.push(QFileEntry {
-            name,
-            comment,
+            name: String::new(),
+            comment: String::new(),
+            name_raw,
+            comment_raw,
             size,
             time,
             date,
         });
     }
 
-    Ok((header, files, pos))
+    // Auto-detection looks at all names and comments together
+    let codepage = codepage.resolve(&raw_strings(&files));
+    for f in &mut files {
+        f.name = codepage.decode(&f.name_raw);
+        f.comment = codepage.decode(&f.comment_raw);
+    }
+
+    Ok((header, files, reader.pos))
+}
+
+/// All raw names and comments of an archive, for code page detection
+fn raw_strings(files: &[QFileEntry]) -> Vec<&[u8]> {
+    files
+        .iter()
+        .flat_map(|f| [&f.name_raw[..], &f.comment_raw[..]])
+        .collect()
 }
 
 // ============================================================================
@@ -671,16 +1020,100 @@ Based on Quantum v0.97 by David Stafford / Cinematronics (1993-1995).
 Algorithm: LZ77 + arithmetic coding with adaptive frequency models.
 
 USAGE:
-    unquantum [OPTIONS] <archive.q>
+    unquantum <COMMAND> [OPTIONS] <archive.q|disk.img>... [PATTERN|@LISTFILE]...
+    unquantum [OPTIONS] <archive.q|disk.img>... [PATTERN|@LISTFILE]...
+    unquantum [OPTIONS] -r <DIR> [PATTERN|@LISTFILE]...
+
+COMMANDS:
+    list      List archive contents (same as -l)
+    info      Show detailed archive information (same as -i)
+    test      Test archive integrity (same as -t)
+    extract   Extract files (same as -x, the default)
+    scan      Find Quantum archives in files, directories and disk images
+    create    Not supported: UnQuantum only reads archives
+Use 'unquantum COMMAND --help' for the options of one command.
 
 OPTIONS:
     -x, --extract     Extract files (default action)
+    -p, --pipe        Extract files to standard output
+        --pipe-header With -p, precede each file with a '==> NAME <==' line
     -l, --list        List archive contents
     -t, --test        Test archive integrity
     -i, --info        Show detailed archive information
     -d, --dirs        Restore directory structure from paths
     -o, --output DIR  Output directory for extracted files
+        --compare DIR Decode and compare with the files in DIR: identical,
+                      differs, missing, different mtime, or extra on disk
+    -r, --recursive DIR
+                      Process every archive below DIR (.Q files in any case,
+                      or files with a Quantum signature)
+        --archive-dirs
+                      Extract each archive into a subdirectory named after it
+    -j, --jobs N      Process N inputs at a time (0: one per CPU); output
+                      stays in input order
     -v, --verbose     Verbose output during extraction
+    -q, --quiet       Print only errors, warnings and the requested listing
+        --progress    Show bytes decoded, current file, speed and time left
+        --image       Treat the input as a FAT12/FAT16 disk image
+        --force       Decode archives with an unsupported version anyway
+        --profile P   Decoder profile: 0.97, cab, or NAME:key=value,...
+                      (keys: threshold, increment, shift, reset, model4,
+                      model5, minlen)
+        --format F    Output of -l, -i and -t: text (default), json, or
+                      ndjson (one JSON record per line); for -l and -i
+                      also csv or tsv, one table across all archives
+        --hashes      Add CRC-32 and SHA-256 columns to csv/tsv output
+        --sort KEY    Order the -l listing by name, size, date or index
+        --reverse     Reverse the order of the -l listing
+        --columns LIST
+                      Extra -l columns: index, packed, ratio (estimated
+                      compressed size, decodes the archive), depth,
+                      comment-length
+        --date-format F
+                      Dates of -l and -i: dmy (default), mdy, iso, or
+                      locale (from LC_ALL/LC_TIME/LANG)
+        --codepage CP Code page of names and comments: cp437 (default),
+                      cp850, cp852, cp866, shift-jis, or auto
+        --utc         Archived times are UTC (default: local time)
+        --overwrite   Replace existing files (default)
+        --skip-existing
+                      Keep existing files, do not extract them
+        --rename-existing
+                      Extract as FOO~1.TXT when FOO.TXT exists
+        --no-clobber  Treat an existing file as an error
+    -f, --freshen     Only replace existing files older than the archive copy
+    -u, --update      Like --freshen, and also extract missing files
+        --duplicates P
+                      Entries extracting to the same file: keep the first,
+                      keep the last (default), or number them
+        --all-or-nothing
+                      Write nothing unless the whole archive decodes and
+                      every file can be extracted
+        --case-insensitive, --case-sensitive
+                      Whether names differing only in case collide in the
+                      output directory (default: by platform)
+        --lowercase   Write file and directory names in lower case
+        --portable-names
+                      Replace characters and device names (CON, NUL, COM1)
+                      that Windows rejects, on every platform
+        --strip-components N
+                      With -d, drop the first N directory levels
+        --name-template T
+                      Rename files, e.g. '{{index:03}}_{{name}}'
+                      (fields: index, index:0N, name, stem, ext)
+        --index R     Select entries by index as shown by -i, e.g. 2-5,8
+        --include P   Select entries matching a pattern (or @LISTFILE),
+                      for patterns that would be taken for an archive
+        --exclude P   Skip entries matching a pattern (or @LISTFILE)
+        --newer-than D, --older-than D
+                      Only entries dated after/before D, given as
+                      YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]
+        --min-size N, --max-size N
+                      Only entries of at least/at most N bytes (K, M suffix)
+        --comment TEXT
+                      Only entries whose comment contains TEXT
+        --comment-regex RE
+                      Only entries whose comment matches RE (. [] * + ? ^ $)
     -h, --help        Show this help message
 
 EXAMPLES:
@@ -689,332 +1122,3200 @@ EXAMPLES:
     unquantum -i archive.q           Show archive details
     unquantum -x -d -o out archive.q Extract with directories to 'out/'
     unquantum -t archive.q           Test archive integrity
+    unquantum -t disk1.img           Test every .Q archive on a floppy image
+    unquantum -t -r /mnt/cdrom       Test every archive on a CD
+    unquantum a.q '*.TXT' 'DOCS\*'   Extract only matching entries
+    unquantum -d --compare game a.q  Check files on disk against a.q
+    unquantum -p a.q README.TXT | less
+                                     View a file without extracting it
+
+Use - as the archive name to read it from standard input.
+Short options can be combined (-xdv, -o out or -oout); -- ends the options.
+Floppy (.img/.ima) and MBR hard-disk images are detected automatically.
+Archives inside them are found by .Q extension or by signature.
+Patterns use DOS wildcards (* and ?) and ignore case. A pattern without a
+separator matches the file name in any directory.
+
+EXIT STATUS:
+    0  success                      4  not a Quantum archive, bad header
+    1  some archives failed           or unsupported version
+    2  invalid command line         5  decoding error
+    3  cannot read or write a file  6  compressed data ends early
+                                    7  files differ (--compare)
+
+The 16-bit checksums stored between files are not verified (their algorithm
+is unknown): an archive that tests clean can still hold damaged contents.
 
 Author: David Carrero Fernandez-Baillo (https://carrero.es)
 License: MIT | https://github.com/dcarrero/unquantum"#
     );
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum Action {
     Extract,
+    /// Extract to standard output
+    Pipe,
     List,
     Test,
     Info,
+    /// Report where archives are, without decoding them
+    Scan,
+    /// Decode and compare with the files in a directory
+    Compare,
 }
 
-struct Config {
-    action: Action,
-    archive_path: String,
-    output_dir: Option<String>,
-    restore_dirs: bool,
-    verbose: bool,
+/// A command word (`unquantum list ...`): the action it selects, if it is
+/// supported, and its own help text
+struct Command {
+    name: &'static str,
+    action: Option<Action>,
+    help: &'static str,
 }
 
-fn parse_args() -> Result<Config, String> {
-    let args: Vec<String> = env::args().collect();
+const COMMANDS: [Command; 6] = [
+    Command {
+        name: "list",
+        action: Some(Action::List),
+        help: r#"unquantum list - list archive contents (same as -l)
 
-    if args.len() < 2 {
-        print_usage();
-        process::exit(1);
-    }
+USAGE:
+    unquantum list [OPTIONS] <archive.q|disk.img>... [PATTERN|@LISTFILE]...
 
-    let mut action = Action::Extract;
-    let mut archive_path = None;
-    let mut output_dir = None;
-    let mut restore_dirs = false;
-    let mut verbose = false;
-    let mut i = 1;
+Shows each entry's size, date and name, reading only the archive header,
+and the compression ratio of the whole archive.
 
-    while i < args.len() {
-        match args[i].as_str() {
-            "-h" | "--help" => {
-                print_usage();
-                process::exit(0);
-            }
-            "-x" | "--extract" => action = Action::Extract,
-            "-l" | "--list" => action = Action::List,
-            "-t" | "--test" => action = Action::Test,
-            "-i" | "--info" => action = Action::Info,
-            "-d" | "--dirs" => restore_dirs = true,
-            "-v" | "--verbose" => verbose = true,
-            "-o" | "--output" => {
-                i += 1;
-                if i >= args.len() {
-                    return Err("-o/--output requires an argument".to_string());
-                }
-                output_dir = Some(args[i].clone());
-            }
-            arg if arg.starts_with('-') => {
-                return Err(format!("Unknown option: {}", arg));
+OPTIONS:
+    -r, --recursive DIR  List every archive below DIR
+        --format F       text (default), json, ndjson, csv or tsv
+        --hashes         With csv/tsv, add CRC-32 and SHA-256 columns
+        --sort KEY       Order by name, size, date or index (default)
+        --reverse        Reverse the order
+        --columns LIST   Add columns: index, packed, ratio, depth,
+                         comment-length (packed and ratio decode the
+                         archive to estimate each file's compressed size)
+        --date-format F  dmy (default), mdy, iso or locale
+        --codepage CP    Code page of names and comments
+        --utc            Archived times are UTC (default: local time)
+        --include P, --index R, --exclude P, --newer-than D,
+        --older-than D, --min-size N, --max-size N, --comment T,
+        --comment-regex RE
+                         Choose entries (see unquantum --help)
+    -j, --jobs N         Process N inputs at a time
+    -q, --quiet          Print only errors, warnings and the listing"#,
+    },
+    Command {
+        name: "info",
+        action: Some(Action::Info),
+        help: r#"unquantum info - show detailed archive information (same as -i)
+
+USAGE:
+    unquantum info [OPTIONS] <archive.q|disk.img>...
+
+Shows the header fields, compression ratio, decoder profile, code page and
+every entry with its index, as used by --index.
+
+OPTIONS:
+    -r, --recursive DIR  Describe every archive below DIR
+        --format F       text (default), json, ndjson, csv or tsv
+        --hashes         With csv/tsv, add CRC-32 and SHA-256 columns
+        --profile P      Decoder profile to report
+        --date-format F  dmy (default), mdy, iso or locale
+        --codepage CP    Code page of names and comments
+        --utc            Archived times are UTC (default: local time)
+    -j, --jobs N         Process N inputs at a time
+    -q, --quiet          Print only errors, warnings and the information"#,
+    },
+    Command {
+        name: "test",
+        action: Some(Action::Test),
+        help: r#"unquantum test - test archive integrity (same as -t)
+
+USAGE:
+    unquantum test [OPTIONS] <archive.q|disk.img>... [PATTERN|@LISTFILE]...
+
+Decodes the selected entries without writing anything. The exit status
+tells a missing file (3) from a damaged archive (4 to 6). The checksums
+stored in the archive are not verified, so a damaged archive that still
+decodes passes.
+
+OPTIONS:
+    -r, --recursive DIR  Test every archive below DIR
+        --format F       text (default), json or ndjson
+        --force          Decode archives with an unsupported version anyway
+        --profile P      Decoder profile: 0.97, cab, or NAME:key=value,...
+        --include P, --index R, --exclude P, --newer-than D,
+        --older-than D, --min-size N, --max-size N, --comment T,
+        --comment-regex RE
+                         Choose entries (see unquantum --help)
+        --progress       Show decoding progress
+    -j, --jobs N         Process N inputs at a time
+    -q, --quiet          Print only errors and warnings"#,
+    },
+    Command {
+        name: "extract",
+        action: Some(Action::Extract),
+        help: r#"unquantum extract - extract files (same as -x, the default)
+
+USAGE:
+    unquantum extract [OPTIONS] <archive.q|disk.img>... [PATTERN|@LISTFILE]...
+
+OPTIONS:
+    -o, --output DIR     Output directory (default: current directory)
+    -d, --dirs           Restore directory structure from paths
+    -p, --pipe           Write files to standard output instead
+        --pipe-header    With -p, precede each file with a header line
+    -r, --recursive DIR  Extract every archive below DIR
+        --archive-dirs   Extract each archive into its own subdirectory
+        --overwrite, --skip-existing, --rename-existing, --no-clobber
+                         What to do with existing files
+    -f, --freshen        Only replace older existing files
+    -u, --update         Like --freshen, and also extract missing files
+        --all-or-nothing Write nothing unless everything can be extracted
+        --duplicates P, --lowercase, --portable-names,
+        --strip-components N, --name-template T,
+        --case-insensitive, --case-sensitive
+                         Name handling (see unquantum --help)
+        --include P, --index R, --exclude P, --newer-than D,
+        --older-than D, --min-size N, --max-size N, --comment T,
+        --comment-regex RE
+                         Choose entries (see unquantum --help)
+        --force, --profile P, --codepage CP, --utc
+                         Decoding (see unquantum --help)
+        --progress       Show decoding progress
+    -j, --jobs N         Process N inputs at a time
+    -v, --verbose        Verbose output
+    -q, --quiet          Print only errors and warnings"#,
+    },
+    Command {
+        name: "scan",
+        action: Some(Action::Scan),
+        help: r#"unquantum scan - find Quantum archives
+
+USAGE:
+    unquantum scan [OPTIONS] <FILE|DIR|disk.img>...
+
+Prints one line per archive found: where it is, its version, number of
+files and sizes. Directories are searched recursively for .Q files, files
+with a Quantum signature and disk images; disk images are searched for
+archives. Nothing is decoded.
+
+OPTIONS:
+        --image          Treat files as FAT12/FAT16 disk images
+        --codepage CP    Code page of names inside disk images
+    -j, --jobs N         Process N inputs at a time
+    -q, --quiet          Leave out the final count"#,
+    },
+    Command {
+        name: "create",
+        action: None,
+        help: r#"unquantum create - not supported
+
+UnQuantum only reads Quantum archives; the compressor was never
+reimplemented. Create archives with the original PAQ.EXE, for example
+under DOSBox."#,
+    },
+];
+
+/// An option that may be given more than once, but only with one value:
+/// `-l -t` is an error rather than silently running the test
+struct Choice<T> {
+    value: T,
+    /// The option that set the value, for error messages
+    set_by: Option<String>,
+}
+
+impl<T: PartialEq> Choice<T> {
+    fn new(default: T) -> Self {
+        Choice {
+            value: default,
+            set_by: None,
+        }
+    }
+
+    fn set(&mut self, value: T, option: &str) -> Result<(), String> {
+        match &self.set_by {
+            Some(previous) if self.value != value => {
+                Err(format!("{} conflicts with {}", option, previous))
             }
             _ => {
-                if archive_path.is_none() {
-                    archive_path = Some(args[i].clone());
-                } else {
-                    return Err(format!("Unexpected argument: {}", args[i]));
-                }
+                self.value = value;
+                self.set_by = Some(option.to_string());
+                Ok(())
             }
         }
-        i += 1;
     }
+}
 
-    let archive_path = archive_path.ok_or("No archive file specified")?;
+/// What to do when an extracted file already exists
+#[derive(Clone, Copy, PartialEq)]
+enum OverwritePolicy {
+    Overwrite,
+    Skip,
+    /// Write FOO~1.TXT, FOO~2.TXT, ... next to the existing file
+    Rename,
+    Fail,
+}
 
-    Ok(Config {
-        action,
-        archive_path,
-        output_dir,
-        restore_dirs,
-        verbose,
-    })
+/// Which entries to extract, relative to the files already on disk
+#[derive(Clone, Copy, PartialEq)]
+enum UpdateMode {
+    /// Extract every entry
+    All,
+    /// Only replace existing files that are older than the archived entry
+    Freshen,
+    /// Like Freshen, and also create files that do not exist yet
+    Update,
 }
 
-/// Display the archive file listing
-fn do_list(header: &QArchiveHeader, files: &[QFileEntry]) {
-    println!(
-        "Quantum {}.{:02} archive - {} file(s)",
-        header.major_version, header.minor_version, header.num_files
-    );
-    println!();
-    println!(
-        " {:>10}  {:>10}  {:>8}  {:<24}  {}",
-        "Size", "Date", "Time", "Name", "Comment"
-    );
-    println!(
-        " {:>10}  {:>10}  {:>8}  {:<24}  {}",
-        "----------", "----------", "--------", "------------------------", "-------"
-    );
+/// Which entry wins when several extract to the same file
+#[derive(Clone, Copy, PartialEq)]
+enum DuplicatePolicy {
+    KeepFirst,
+    KeepLast,
+    /// Keep all of them as FOO.TXT, FOO~1.TXT, ...
+    Number,
+}
 
-    let mut total_size: u64 = 0;
-    for f in files {
-        println!(
-            " {:>10}  {:>10}  {:>8}  {:<24}  {}",
-            f.size,
-            f.date_string(),
-            f.time_string(),
-            f.name,
-            f.comment
-        );
-        total_size += f.size as u64;
+impl DuplicatePolicy {
+    fn parse(name: &str) -> Result<DuplicatePolicy, String> {
+        match name {
+            "first" => Ok(DuplicatePolicy::KeepFirst),
+            "last" => Ok(DuplicatePolicy::KeepLast),
+            "number" => Ok(DuplicatePolicy::Number),
+            _ => Err(format!(
+                "Unknown duplicates policy '{}' (known: first, last, number)",
+                name
+            )),
+        }
     }
 
-    println!(
-        " {:>10}  {:>10}  {:>8}  {} file(s)",
-        total_size, "", "", files.len()
-    );
+    fn describe(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::KeepFirst => "keeping the first",
+            DuplicatePolicy::KeepLast => "keeping the last",
+            DuplicatePolicy::Number => "numbering the others",
+        }
+    }
 }
 
-/// Display detailed archive information
-fn do_info(
-    header: &QArchiveHeader,
-    files: &[QFileEntry],
-    archive_size: usize,
-) {
-    let total_original: u64 = files.iter().map(|f| f.size as u64).sum();
-    let window_size = 1u64 << header.table_size;
-    let window_kb = window_size / 1024;
+/// Order of the entries in the text listing
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    /// Archive order
+    Index,
+    Name,
+    Size,
+    Date,
+}
 
-    println!("=== Quantum Archive Information ===");
-    println!();
-    println!(
-        "Version:           {}.{:02}",
-        header.major_version, header.minor_version
-    );
-    println!("Number of files:   {}", header.num_files);
-    println!(
-        "Table size:        {} (window = {} KB = {} bytes)",
-        header.table_size, window_kb, window_size
-    );
-    println!("Compression flags: 0x{:02X}", header.comp_flags);
-    println!("Archive size:      {} bytes", archive_size);
-    println!("Original size:     {} bytes", total_original);
-    if total_original > 0 {
-        let ratio = (archive_size as f64 / total_original as f64) * 100.0;
-        println!("Compression ratio: {:.1}%", ratio);
-    }
-    println!();
-    println!("--- Files ---");
-    for (idx, f) in files.iter().enumerate() {
-        let comment_str = if f.comment.is_empty() {
-            String::new()
-        } else {
-            format!(" \"{}\"", f.comment)
-        };
-        println!(
-            "  [{}] {} ({} bytes) {} {}{}",
-            idx,
-            f.name,
-            f.size,
-            f.date_string(),
-            f.time_string(),
-            comment_str
-        );
+impl SortKey {
+    fn parse(name: &str) -> Result<SortKey, String> {
+        match name {
+            "index" => Ok(SortKey::Index),
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "date" => Ok(SortKey::Date),
+            _ => Err(format!(
+                "Unknown sort key '{}' (known: name, size, date, index)",
+                name
+            )),
+        }
     }
 }
 
-/// Extract or test the archive
-fn do_extract_or_test(
-    header: &QArchiveHeader,
-    files: &[QFileEntry],
-    compressed_data: Vec<u8>,
-    config: &Config,
-) -> Result<(), String> {
-    let total_output_size: usize = files.iter().map(|f| f.size as usize).sum();
+/// Optional columns of the text listing
+#[derive(Clone, Copy, PartialEq)]
+enum ListColumn {
+    Index,
+    /// Estimated compressed size
+    Packed,
+    /// Estimated compressed size as a share of the original size
+    Ratio,
+    /// Directory levels in the name
+    Depth,
+    /// Bytes of the stored comment
+    CommentLength,
+}
 
-    if config.verbose || config.action == Action::Test {
-        println!(
-            "Quantum {}.{:02} archive - {} file(s), table size {}",
-            header.major_version,
-            header.minor_version,
-            header.num_files,
-            header.table_size
-        );
-        println!("Total decompressed size: {} bytes", total_output_size);
-        println!("Compressed data size:    {} bytes", compressed_data.len());
-        println!();
+impl ListColumn {
+    /// A comma-separated list of column names
+    fn parse_list(list: &str) -> Result<Vec<ListColumn>, String> {
+        list.split(',')
+            .map(|name| match name.trim() {
+                "index" => Ok(ListColumn::Index),
+                "packed" => Ok(ListColumn::Packed),
+                "ratio" => Ok(ListColumn::Ratio),
+                "depth" => Ok(ListColumn::Depth),
+                "comment-length" => Ok(ListColumn::CommentLength),
+                other => Err(format!(
+                    "Unknown column '{}' (known: index, packed, ratio, depth, \
+                     comment-length)",
+                    other
+                )),
+            })
+            .collect()
     }
 
-    if total_output_size == 0 {
-        println!("Archive contains no data to extract.");
-        return Ok(());
+    /// Whether the column needs the archive decoded
+    fn decodes(self) -> bool {
+        matches!(self, ListColumn::Packed | ListColumn::Ratio)
     }
+}
 
-    // Decompress the entire data stream
-    if config.verbose {
-        println!("Decompressing...");
-    }
-    let file_sizes: Vec<u32> = files.iter().map(|f| f.size).collect();
-    let decompressed =
-        quantum_decompress(compressed_data, &file_sizes, header.table_size)?;
+struct Config {
+    action: Action,
+    /// Archives and disk images named on the command line
+    inputs: Vec<String>,
+    /// Directories searched recursively for archives (-r)
+    recurse: Vec<String>,
+    output_dir: Option<String>,
+    /// Extract each archive into a subdirectory named after it
+    archive_dirs: bool,
+    name_mapping: paths::NameMapping,
+    verbose: bool,
+    force_image: bool,
+    force: bool,
+    profile: Option<QuantumProfile>,
+    codepage: CodePage,
+    timezone: TimeZone,
+    overwrite: OverwritePolicy,
+    update_mode: UpdateMode,
+    duplicates: DuplicatePolicy,
+    /// Remove everything written if the archive turns out to be bad
+    all_or_nothing: bool,
+    /// Treat names differing only in case as the same output file
+    fold_case: bool,
+    /// Entries to list, test or extract
+    selection: select::Selection,
+    /// With -p, precede each entry's data with a header line
+    pipe_header: bool,
+    /// Output format of list, info and test
+    format: report::Format,
+    /// Add content hashes to --format csv/tsv tables
+    hashes: bool,
+    /// Inputs processed at the same time (-j)
+    jobs: usize,
+    /// Print only errors, warnings and the requested listing or report
+    quiet: bool,
+    /// Show decoding progress on standard error
+    progress: bool,
+    /// Order of the text listing
+    sort: SortKey,
+    reverse: bool,
+    /// How -l and -i write dates
+    date_format: DateFormat,
+    /// Optional columns of the text listing
+    columns: Vec<ListColumn>,
+    /// Started as `unpaq`: listings and per-file lines in UNPAQ's layout
+    unpaq: bool,
+}
 
-    if decompressed.len() != total_output_size {
-        return Err(format!(
-            "Decompression size mismatch: expected {} bytes, got {}",
-            total_output_size,
-            decompressed.len()
-        ));
+impl Config {
+    /// Whether the action needs the compressed data, not just the header
+    fn reads_data(&self) -> bool {
+        self.action != Action::List
+            || self.hashes
+            || self.columns.iter().any(|c| c.decodes())
     }
+}
+
+/// Print a status line: to stdout normally, to stderr when stdout carries
+/// file data (-p) or a machine-readable report (--format), not at all with -q
+macro_rules! status {
+    ($config:expr $(, $($arg:tt)*)?) => {
+        if !$config.quiet {
+            if $config.action == Action::Pipe
+                || $config.format != report::Format::Text
+            {
+                errln!($($($arg)*)?)
+            } else {
+                outln!($($($arg)*)?)
+            }
+        }
+    };
+}
+
+fn parse_args(mut args: Vec<String>) -> Result<Config, String> {
+    if args.len() < 2 {
+        print_usage();
+        process::exit(EXIT_USAGE);
+    }
+
+    // A command word comes first; without one, the action flags (-l, -t,
+    // ...) select the action as they always have
+    let mut action = Choice::new(Action::Extract);
+    let mut pipe = None;
+    let command = COMMANDS.iter().find(|c| c.name == args[1]);
+    let mut i = 1;
+    if let Some(command) = command {
+        let help = args[2..].iter().any(|a| a == "-h" || a == "--help");
+        match command.action {
+            Some(a) => action.set(a, command.name)?,
+            None if help => {}
+            None => {
+                return Err(format!(
+                    "'{}' is not supported: UnQuantum only reads Quantum \
+                     archives (see unquantum {} --help)",
+                    command.name, command.name
+                ));
+            }
+        }
+        i = 2;
+    }
+
+    let mut operands: Vec<String> = Vec::new();
+    // Operands from here on came after "--"
+    let mut literal_from = usize::MAX;
+    let mut recurse = Vec::new();
+    let mut archive_dirs = false;
+    let mut output_dir = None;
+    let mut compare_dir = None;
+    let mut restore_dirs = false;
+    let mut strip_components = 0usize;
+    let mut template = None;
+    let mut lowercase = false;
+    let mut portable = false;
+    let mut verbose = false;
+    let mut force_image = false;
+    let mut force = false;
+    let mut profile = None;
+    let mut codepage = CodePage::Cp437;
+    let mut timezone = TimeZone::Local;
+    let mut overwrite = Choice::new(OverwritePolicy::Overwrite);
+    let mut update_mode = Choice::new(UpdateMode::All);
+    let mut duplicates = Choice::new(DuplicatePolicy::KeepLast);
+    let mut all_or_nothing = false;
+    let mut selection = select::Selection::default();
+    let mut pipe_header = false;
+    let mut format = Choice::new(report::Format::Text);
+    let mut hashes = false;
+    let mut jobs = 1;
+    let mut quiet = false;
+    let mut progress = false;
+    let mut sort = Choice::new(SortKey::Index);
+    let mut reverse = false;
+    let mut date_format = Choice::new(DateFormat::Dmy);
+    let mut columns = Vec::new();
+    // Windows and macOS file systems are case-insensitive by default
+    let mut fold_case = Choice::new(cfg!(any(windows, target_os = "macos")));
+
+    while i < args.len() {
+        let option = args[i].clone();
+        match option.as_str() {
+            "-h" | "--help" => {
+                match command {
+                    Some(command) => eprintln!("{}", command.help),
+                    None => print_usage(),
+                }
+                process::exit(0);
+            }
+            "-x" | "--extract" => action.set(Action::Extract, &option)?,
+            "-p" | "--pipe" => pipe = Some(option),
+            "--pipe-header" => pipe_header = true,
+            "-l" | "--list" => action.set(Action::List, &option)?,
+            "-t" | "--test" => action.set(Action::Test, &option)?,
+            "-i" | "--info" => action.set(Action::Info, &option)?,
+            "-d" | "--dirs" => restore_dirs = true,
+            "-v" | "--verbose" => verbose = true,
+            "--image" => force_image = true,
+            "--force" => force = true,
+            "--utc" => timezone = TimeZone::Utc,
+            "--overwrite" => {
+                overwrite.set(OverwritePolicy::Overwrite, &option)?
+            }
+            "--skip-existing" => overwrite.set(OverwritePolicy::Skip, &option)?,
+            "--rename-existing" => {
+                overwrite.set(OverwritePolicy::Rename, &option)?
+            }
+            "--no-clobber" => overwrite.set(OverwritePolicy::Fail, &option)?,
+            "-f" | "--freshen" => update_mode.set(UpdateMode::Freshen, &option)?,
+            "-u" | "--update" => update_mode.set(UpdateMode::Update, &option)?,
+            "--duplicates" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--duplicates requires an argument".to_string());
+                }
+                let policy = DuplicatePolicy::parse(&args[i])?;
+                duplicates.set(policy, &format!("--duplicates {}", args[i]))?;
+            }
+            "--all-or-nothing" => all_or_nothing = true,
+            "--case-insensitive" => fold_case.set(true, &option)?,
+            "--case-sensitive" => fold_case.set(false, &option)?,
+            "--lowercase" => lowercase = true,
+            "--portable-names" => portable = true,
+            "--strip-components" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err(
+                        "--strip-components requires an argument".to_string()
+                    );
+                }
+                strip_components = args[i].parse().map_err(|_| {
+                    format!("Invalid --strip-components value '{}'", args[i])
+                })?;
+            }
+            "--name-template" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--name-template requires an argument".to_string());
+                }
+                template = Some(paths::NameTemplate::parse(&args[i])?);
+            }
+            "--profile" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--profile requires an argument".to_string());
+                }
+                profile = Some(QuantumProfile::parse(&args[i])?);
+            }
+            "--format" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--format requires an argument".to_string());
+                }
+                let value = report::Format::parse(&args[i])?;
+                format.set(value, &format!("--format {}", args[i]))?;
+            }
+            "--hashes" => hashes = true,
+            "--sort" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--sort requires an argument".to_string());
+                }
+                let key = SortKey::parse(&args[i])?;
+                sort.set(key, &format!("--sort {}", args[i]))?;
+            }
+            "--reverse" => reverse = true,
+            "--date-format" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--date-format requires an argument".to_string());
+                }
+                let value = DateFormat::parse(&args[i])?;
+                date_format.set(value, &format!("--date-format {}", args[i]))?;
+            }
+            "--columns" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--columns requires an argument".to_string());
+                }
+                columns.extend(ListColumn::parse_list(&args[i])?);
+            }
+            "--archive-dirs" => archive_dirs = true,
+            "-q" | "--quiet" => quiet = true,
+            "--progress" => progress = true,
+            "-j" | "--jobs" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("-j/--jobs requires an argument".to_string());
+                }
+                jobs = args[i]
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid job count '{}'", args[i]))?;
+                // 0 means one job per available CPU
+                if jobs == 0 {
+                    jobs = std::thread::available_parallelism()
+                        .map_or(1, |n| n.get());
+                }
+            }
+            "-r" | "--recursive" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("-r/--recursive requires an argument".to_string());
+                }
+                recurse.push(args[i].clone());
+            }
+            "--codepage" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--codepage requires an argument".to_string());
+                }
+                codepage = CodePage::parse(&args[i])?;
+            }
+            "--exclude" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--exclude requires an argument".to_string());
+                }
+                match args[i].strip_prefix('@') {
+                    Some(list) => {
+                        selection.exclude.extend(select::read_list_file(list)?)
+                    }
+                    None => selection.exclude.push(args[i].clone()),
+                }
+            }
+            "--include" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--include requires an argument".to_string());
+                }
+                match args[i].strip_prefix('@') {
+                    Some(list) => {
+                        selection.include.extend(select::read_list_file(list)?)
+                    }
+                    None => selection.include.push(args[i].clone()),
+                }
+            }
+            "--index" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--index requires an argument".to_string());
+                }
+                selection.add_ranges(&args[i])?;
+            }
+            "--newer-than" | "--older-than" => {
+                let option = args[i].clone();
+                i += 1;
+                if i >= args.len() {
+                    return Err(format!("{} requires an argument", option));
+                }
+                let datetime = DosDateTime::parse(&args[i])?;
+                if option == "--newer-than" {
+                    selection.newer_than = Some(datetime);
+                } else {
+                    selection.older_than = Some(datetime);
+                }
+            }
+            "--min-size" | "--max-size" => {
+                let option = args[i].clone();
+                i += 1;
+                if i >= args.len() {
+                    return Err(format!("{} requires an argument", option));
+                }
+                let size = select::parse_size(&args[i])?;
+                if option == "--min-size" {
+                    selection.min_size = Some(size);
+                } else {
+                    selection.max_size = Some(size);
+                }
+            }
+            "--comment" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--comment requires an argument".to_string());
+                }
+                selection.comment = Some(args[i].to_lowercase());
+            }
+            "--comment-regex" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--comment-regex requires an argument".to_string());
+                }
+                selection.comment_regex = Some(select::Pattern::parse(&args[i])?);
+            }
+            "--compare" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("--compare requires an argument".to_string());
+                }
+                action.set(Action::Compare, &option)?;
+                compare_dir = Some(args[i].clone());
+            }
+            "-o" | "--output" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("-o/--output requires an argument".to_string());
+                }
+                output_dir = Some(args[i].clone());
+            }
+            // Everything after "--" is an operand, even if it starts with "-"
+            "--" => {
+                literal_from = operands.len();
+                operands.extend(args.drain(i + 1..));
+                break;
+            }
+            // Combined short options: -xdv is -x -d -v, and an option that
+            // takes a value takes the rest (-ofoo is -o foo)
+            arg if arg.len() > 2 && arg.starts_with('-') && !arg.starts_with("--") => {
+                let split = 1 + arg[1..].chars().next().map_or(1, char::len_utf8);
+                let (flag, rest) = arg.split_at(split);
+                let rest = match flag {
+                    "-o" | "-r" | "-j" => rest.to_string(),
+                    _ => format!("-{}", rest),
+                };
+                args.splice(i..=i, [flag.to_string(), rest]);
+                continue;
+            }
+            // A lone "-" is standard input, not an option
+            arg if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option: {}", arg));
+            }
+            _ => operands.push(args[i].clone()),
+        }
+        i += 1;
+    }
+
+    // -p extracts to standard output instead of files
+    let action = match pipe {
+        Some(flag) if action.value != Action::Extract => {
+            let previous = action.set_by.unwrap_or_default();
+            return Err(format!("{} conflicts with {}", flag, previous));
+        }
+        Some(_) => Action::Pipe,
+        None => action.value,
+    };
+    let format = format.value;
+
+    // The first operand is an archive unless -r supplies them. Further
+    // operands before "--" are archives too when they name one (or a disk
+    // image) on disk, or look like one; everything else selects entries.
+    // scan takes only files and directories to search, searched where they
+    // stand.
+    let mut inputs = Vec::new();
+    for (n, operand) in operands.into_iter().enumerate() {
+        if action == Action::Scan || (n == 0 && recurse.is_empty()) {
+            inputs.push(operand);
+        } else if let Some(list) = operand.strip_prefix('@') {
+            selection.include.extend(select::read_list_file(list)?);
+        } else if n < literal_from
+            && (is_input_file(Path::new(&operand))
+                || looks_like_input_name(&operand))
+        {
+            inputs.push(operand);
+        } else {
+            selection.include.push(operand);
+        }
+    }
+    if inputs.is_empty() && recurse.is_empty() {
+        return Err("No archive file specified".to_string());
+    }
+    if inputs.iter().filter(|p| *p == "-").count() > 1 {
+        return Err("Standard input can only be read once".to_string());
+    }
+    if format != report::Format::Text
+        && matches!(
+            action,
+            Action::Extract | Action::Pipe | Action::Scan | Action::Compare
+        )
+    {
+        return Err("--format applies to list, info and test only".to_string());
+    }
+    if format.is_table() && action == Action::Test {
+        return Err(
+            "--format csv/tsv applies to -l and -i (use json or ndjson with -t)"
+                .to_string(),
+        );
+    }
+    if hashes && !format.is_table() {
+        return Err(
+            "--hashes applies to --format csv/tsv (-t reports hashes in json)"
+                .to_string(),
+        );
+    }
+    // --compare reads the directory -o would write to
+    if let Some(dir) = compare_dir {
+        if output_dir.is_some() {
+            return Err("--compare conflicts with -o/--output".to_string());
+        }
+        if !Path::new(&dir).is_dir() {
+            return Err(format!(
+                "Cannot compare with '{}': not a directory",
+                dir
+            ));
+        }
+        output_dir = Some(dir);
+    }
+    if strip_components > 0 && !restore_dirs {
+        return Err("--strip-components requires -d (without it only file \
+                    names are kept)"
+            .to_string());
+    }
+    if jobs > 1 && action == Action::Pipe {
+        return Err("-j cannot be used with -p (entries must stay in order)"
+            .to_string());
+    }
+    if jobs > 1 && progress {
+        return Err("--progress cannot be used with -j".to_string());
+    }
+    let listing = action == Action::List && format == report::Format::Text;
+    if (sort.set_by.is_some() || reverse || !columns.is_empty()) && !listing {
+        return Err(
+            "--sort, --reverse and --columns apply to the text listing (-l)"
+                .to_string(),
+        );
+    }
+    if date_format.set_by.is_some()
+        && !(matches!(action, Action::List | Action::Info)
+            && format == report::Format::Text)
+    {
+        return Err(
+            "--date-format applies to -l and -i (--format reports use ISO 8601)"
+                .to_string(),
+        );
+    }
+    if date_format.value == DateFormat::Locale {
+        dostime::use_locale_dates();
+    }
+
+    Ok(Config {
+        action,
+        inputs,
+        recurse,
+        output_dir,
+        archive_dirs,
+        name_mapping: paths::NameMapping {
+            restore_dirs,
+            strip_components,
+            template,
+            lowercase,
+            portable,
+        },
+        verbose,
+        force_image,
+        force,
+        profile,
+        codepage,
+        timezone,
+        overwrite: overwrite.value,
+        update_mode: update_mode.value,
+        duplicates: duplicates.value,
+        all_or_nothing,
+        fold_case: fold_case.value,
+        selection,
+        pipe_header,
+        format,
+        hashes,
+        jobs,
+        quiet,
+        progress,
+        sort: sort.value,
+        reverse,
+        date_format: date_format.value,
+        columns,
+        unpaq: legacy::is_unpaq(&args[0]),
+    })
+}
+
+/// The columns of the text listing before the name: (column, header,
+/// width). Optional columns are left out unless chosen with --columns.
+const LIST_CELLS: [(Option<ListColumn>, &str, usize); 8] = [
+    (Some(ListColumn::Index), "Index", 5),
+    (None, "Size", 10),
+    (Some(ListColumn::Packed), "Packed", 10),
+    (Some(ListColumn::Ratio), "Ratio", 6),
+    (None, "Date", 10),
+    (None, "Time", 8),
+    (Some(ListColumn::Depth), "Depth", 5),
+    (Some(ListColumn::CommentLength), "CmtLen", 6),
+];
+
+/// The cells of a listing line, laid out with LIST_CELLS, up to the name.
+/// Dates may be wider than 10 characters in some locales.
+fn list_cells(
+    config: &Config,
+    date_width: usize,
+    cells: [String; 8],
+) -> String {
+    let mut line = String::from(" ");
+    for ((column, header, width), cell) in LIST_CELLS.iter().zip(cells) {
+        if column.is_some_and(|c| !config.columns.contains(&c)) {
+            continue;
+        }
+        let width = if *header == "Date" { date_width } else { *width };
+        line.push_str(&format!("{:>width$}  ", cell, width = width));
+    }
+    line
+}
+
+/// Estimated compressed size as a percentage of the original size
+fn ratio_cell(packed: u64, size: u64) -> String {
+    if size == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", packed as f64 * 100.0 / size as f64)
+    }
+}
+
+/// Display the archive file listing. The packed and ratio columns decode
+/// the archive; entries the decoder did not reach show `?`.
+fn do_list(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    archive_size: usize,
+    config: &Config,
+) -> Result<(), Failure> {
+    // A listing read from a pipe may not have the compressed data
+    let total_original: u64 = files.iter().map(|f| f.size as u64).sum();
+    let overall_ratio = (!compressed_data.is_empty() && total_original > 0)
+        .then(|| archive_size as f64 * 100.0 / total_original as f64);
+
+    let mut packed: Vec<Option<u64>> = vec![None; files.len()];
+    let mut result = Ok(());
+    if config.columns.iter().any(|c| c.decodes()) {
+        result = decode_entries(
+            header,
+            files,
+            selected,
+            compressed_data,
+            config,
+            |index, _, _, _, bits| {
+                packed[index] = Some(bits.div_ceil(8));
+                Ok(())
+            },
+        );
+    }
+
+    let mut order: Vec<usize> =
+        (0..files.len()).filter(|&i| selected[i]).collect();
+    match config.sort {
+        SortKey::Index => {}
+        SortKey::Name => {
+            order.sort_by_cached_key(|&i| files[i].name.to_lowercase())
+        }
+        SortKey::Size => order.sort_by_key(|&i| files[i].size),
+        SortKey::Date => order.sort_by_key(|&i| (files[i].date, files[i].time)),
+    }
+    if config.reverse {
+        order.reverse();
+    }
+    if config.unpaq {
+        for line in legacy::listing(files, &order) {
+            outln!("{}", line);
+        }
+        return result;
+    }
+
+    let dates: Vec<String> =
+        files.iter().map(|f| f.date_string(config.date_format)).collect();
+    let date_width = order
+        .iter()
+        .map(|&i| dates[i].chars().count())
+        .fold(10, usize::max);
+
+    outln!(
+        "Quantum {}.{:02} archive - {} file(s)",
+        header.major_version, header.minor_version, header.num_files
+    );
+    outln!();
+    let headers = LIST_CELLS.map(|(_, header, _)| header.to_string());
+    outln!(
+        "{}{:<24}  Comment",
+        list_cells(config, date_width, headers),
+        "Name"
+    );
+    let rules = LIST_CELLS.map(|(_, header, width)| {
+        let width = if header == "Date" { date_width } else { width };
+        "-".repeat(width)
+    });
+    outln!(
+        "{}{}  -------",
+        list_cells(config, date_width, rules),
+        "-".repeat(24)
+    );
+
+    let unknown = || "?".to_string();
+    let mut total_size: u64 = 0;
+    let mut total_packed: u64 = 0;
+    let mut packed_size: u64 = 0;
+    for &index in &order {
+        let f = &files[index];
+        let cells = [
+            index.to_string(),
+            f.size.to_string(),
+            packed[index].map_or_else(unknown, |p| p.to_string()),
+            packed[index]
+                .map_or_else(unknown, |p| ratio_cell(p, f.size as u64)),
+            dates[index].clone(),
+            f.time_string(),
+            f.depth().to_string(),
+            f.comment_raw.len().to_string(),
+        ];
+        outln!(
+            "{}{:<24}  {}",
+            list_cells(config, date_width, cells),
+            f.name,
+            f.comment
+        );
+        total_size += f.size as u64;
+        if let Some(p) = packed[index] {
+            total_packed += p;
+            packed_size += f.size as u64;
+        }
+    }
+
+    let mut summary = format!("{} file(s)", order.len());
+    if let Some(ratio) = overall_ratio {
+        summary.push_str(&format!(", compression ratio {:.1}%", ratio));
+    }
+    let cells = [
+        String::new(),
+        total_size.to_string(),
+        total_packed.to_string(),
+        ratio_cell(total_packed, packed_size),
+        String::new(),
+        String::new(),
+        String::new(),
+        String::new(),
+    ];
+    outln!("{}{}", list_cells(config, date_width, cells), summary);
+    result
+}
+
+/// Display detailed archive information
+fn do_info(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    archive_size: usize,
+    config: &Config,
+) {
+    let total_original: u64 = files.iter().map(|f| f.size as u64).sum();
+    let window_size = 1u64 << header.table_size;
+    let window_kb = window_size / 1024;
+
+    outln!("=== Quantum Archive Information ===");
+    outln!();
+    outln!(
+        "Version:           {}.{:02}",
+        header.major_version, header.minor_version
+    );
+    outln!("Number of files:   {}", header.num_files);
+    outln!(
+        "Table size:        {} (window = {} KB = {} bytes)",
+        header.table_size, window_kb, window_size
+    );
+    outln!(
+        "Compression flags: 0x{:02X} ({})",
+        header.comp_flags,
+        header.flag_names().join(", ")
+    );
+    outln!("Archive size:      {} bytes", archive_size);
+    outln!("Original size:     {} bytes", total_original);
+    if total_original > 0 {
+        let ratio = (archive_size as f64 / total_original as f64) * 100.0;
+        outln!("Compression ratio: {:.1}%", ratio);
+    }
+    let profile = QuantumProfile::select(header, config.profile);
+    outln!("Decoder profile:   {}", profile.describe());
+    let codepage = config.codepage.resolve(&raw_strings(files));
+    if config.codepage == CodePage::Auto {
+        outln!("Code page:         {} (detected)", codepage.name());
+    } else {
+        outln!("Code page:         {}", codepage.name());
+    }
+    match header.compatibility() {
+        Ok(warnings) if warnings.is_empty() => {
+            outln!("Compatibility:     OK");
+        }
+        Ok(warnings) => {
+            for w in warnings {
+                outln!("Compatibility:     warning: {}", w);
+            }
+        }
+        Err(e) => outln!("Compatibility:     error: {}", e),
+    }
+    outln!();
+    outln!("--- Files ---");
+    for (idx, f) in files.iter().enumerate() {
+        let comment_str = if f.comment.is_empty() {
+            String::new()
+        } else {
+            format!(" \"{}\"", f.comment)
+        };
+        outln!(
+            "  [{}] {} ({} bytes) {} {}{}",
+            idx,
+            f.name,
+            f.size,
+            f.date_string(config.date_format),
+            f.time_string(),
+            comment_str
+        );
+    }
+}
+
+/// The details shown by -i, for --format reports
+fn info_json(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    archive_size: usize,
+    config: &Config,
+) -> json::Value {
+    use json::Value;
+    let total_original: u64 = files.iter().map(|f| f.size as u64).sum();
+    let ratio = if total_original > 0 {
+        Value::Float(
+            (archive_size as f64 / total_original as f64 * 1000.0).round() / 10.0,
+        )
+    } else {
+        Value::Null
+    };
+    let profile = QuantumProfile::select(header, config.profile);
+    let codepage = config.codepage.resolve(&raw_strings(files));
+    let (status, messages) = match header.compatibility() {
+        Ok(warnings) if warnings.is_empty() => ("ok", warnings),
+        Ok(warnings) => ("warning", warnings),
+        Err(e) => ("error", vec![e]),
+    };
+    Value::object(vec![
+        ("archive_size", Value::Int(archive_size as i64)),
+        ("original_size", Value::Int(total_original as i64)),
+        ("compression_ratio", ratio),
+        ("decoder_profile", Value::string(profile.describe())),
+        ("codepage", Value::string(codepage.name())),
+        ("codepage_detected", Value::Bool(config.codepage == CodePage::Auto)),
+        (
+            "compatibility",
+            Value::object(vec![
+                ("status", Value::string(status)),
+                (
+                    "messages",
+                    Value::Array(messages.into_iter().map(Value::String).collect()),
+                ),
+            ]),
+        ),
+    ])
+}
+
+/// Where an entry is written, decided before anything touches the disk
+enum PlannedPath {
+    /// Path relative to the output directory
+    Write(PathBuf),
+    /// Duplicate entry, written under a numbered name
+    Numbered(PathBuf),
+    /// Deliberately not extracted, with the reason shown in the file list
+    Skip(&'static str),
+    /// Cannot be extracted; the error has already been reported
+    Fail,
+}
+
+/// Map every archived name to its output path: sanitise it, run it through
+/// the name mapping options (-d, --strip-components, --name-template,
+/// --lowercase, --portable-names), then resolve entries that end up on the
+/// same path according to the --duplicates policy. Unselected entries are
+/// left out.
+fn plan_output_paths(
+    files: &[QFileEntry],
+    selected: &[bool],
+    config: &Config,
+) -> Vec<PlannedPath> {
+    let mut plans: Vec<PlannedPath> = files
+        .iter()
+        .enumerate()
+        .map(|(index, f)| {
+            if !selected[index] {
+                return PlannedPath::Skip("not selected");
+            }
+            // Map the untrusted archived name to a path inside the output
+            let safe = match paths::sanitize(&f.name) {
+                Ok(safe) => safe,
+                Err(e) => {
+                    errln!("Error: {}, skipped", e);
+                    return PlannedPath::Fail;
+                }
+            };
+            if !safe.notes.is_empty() {
+                errln!(
+                    "Warning: '{}' sanitised to '{}' ({})",
+                    f.name,
+                    safe.display(),
+                    safe.notes.join(", ")
+                );
+            }
+            match config.name_mapping.map(index, &safe) {
+                Some(path) => PlannedPath::Write(path),
+                None => PlannedPath::Skip("no path left after --strip-components"),
+            }
+        })
+        .collect();
+
+    let targets = plans.iter().enumerate().filter_map(|(i, plan)| match plan {
+        PlannedPath::Write(path) => Some((i, path.to_string_lossy().into_owned())),
+        _ => None,
+    });
+    for group in paths::find_collisions(targets, config.fold_case) {
+        let names: Vec<String> =
+            group.iter().map(|&i| format!("'{}'", files[i].name)).collect();
+        errln!(
+            "Warning: {} extract to the same file, {}",
+            names.join(", "),
+            config.duplicates.describe()
+        );
+        let (first, last) = (group[0], group[group.len() - 1]);
+        for (n, &i) in group.iter().enumerate() {
+            match config.duplicates {
+                DuplicatePolicy::KeepFirst if i != first => {
+                    plans[i] = PlannedPath::Skip("duplicate, first entry kept");
+                }
+                DuplicatePolicy::KeepLast if i != last => {
+                    plans[i] = PlannedPath::Skip("duplicate, last entry kept");
+                }
+                DuplicatePolicy::Number if n > 0 => {
+                    if let PlannedPath::Write(path) = &plans[i] {
+                        plans[i] =
+                            PlannedPath::Numbered(paths::numbered_name(path, n));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    plans
+}
+
+/// Warn about entries whose names collide: exact duplicates, and names that
+/// differ only in case (which DOS and case-insensitive file systems treat
+/// as the same file)
+fn report_collisions(files: &[QFileEntry]) {
+    let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+    for warning in collision_warnings(&names) {
+        errln!("Warning: {}", warning);
+    }
+}
+
+/// The warnings of `report_collisions`. Within each group of names equal
+/// but for case, exact duplicates are reported on their own, and the
+/// distinct spellings, by their first entry, only if there are several.
+fn collision_warnings(names: &[&str]) -> Vec<String> {
+    // `/` and `\` separate directories alike
+    let keys: Vec<String> = names.iter().map(|n| n.replace('/', "\\")).collect();
+    let list = |group: &[usize]| -> String {
+        let indices: Vec<String> =
+            group.iter().map(|i| i.to_string()).collect();
+        indices.join(", ")
+    };
+    let mut warnings = Vec::new();
+    let folded = keys.iter().cloned().enumerate();
+    for group in paths::find_collisions(folded, true) {
+        let exact = group.iter().map(|&i| (i, keys[i].clone()));
+        for same in paths::find_collisions(exact, false) {
+            warnings.push(format!(
+                "duplicate name '{}' (entries {})",
+                names[same[0]],
+                list(&same)
+            ));
+        }
+        let mut spellings: Vec<usize> = Vec::new();
+        for &i in &group {
+            if spellings.iter().all(|&s| keys[s] != keys[i]) {
+                spellings.push(i);
+            }
+        }
+        if spellings.len() > 1 {
+            let quoted: Vec<String> =
+                spellings.iter().map(|&i| format!("'{}'", names[i])).collect();
+            warnings.push(format!(
+                "names differ only in case: {} (entries {})",
+                quoted.join(", "),
+                list(&spellings)
+            ));
+        }
+    }
+    warnings
+}
+
+/// Freshen/update test: is the archived entry newer than the file on disk?
+/// Differences below the two-second DOS resolution do not count.
+fn is_newer_than(f: &QFileEntry, meta: &fs::Metadata, tz: TimeZone) -> bool {
+    let archived = f.datetime().to_unix(tz);
+    match meta.modified() {
+        Ok(mtime) => {
+            archived - dostime::unix_seconds(mtime) >= dostime::DOS_RESOLUTION
+        }
+        // No usable time on disk: assume the archive copy is newer
+        Err(_) => true,
+    }
+}
+
+/// Set the modification time of a directory
+fn set_dir_modified(dir: &Path, mtime: SystemTime) -> std::io::Result<()> {
+    #[cfg(windows)]
+    let handle = {
+        use std::os::windows::fs::OpenOptionsExt;
+        // Directories can only be opened with backup semantics
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+        fs::OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(dir)?
+    };
+    #[cfg(not(windows))]
+    let handle = File::open(dir)?;
+    handle.set_modified(mtime)
+}
+
+/// Decode the archive file by file. Each selected file is handed to
+/// `on_file` once its data is complete and, for all but the last file, the
+/// checksum that follows it has been consumed with the stream still in sync.
+/// The stored checksum is passed along; its algorithm is undocumented, so it
+/// cannot be verified. So is the estimated compressed size of the file, in
+/// bits (see `QuantumDecoder::bits_consumed`).
+/// The stream is solid, so unselected files before the last selected one are
+/// decoded too; decoding stops after the last selected file.
+fn decode_entries(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    config: &Config,
+    mut on_file: impl FnMut(
+        usize,
+        &QFileEntry,
+        &[u8],
+        Option<u16>,
+        u64,
+    ) -> Result<(), String>,
+) -> Result<(), Failure> {
+    let profile = QuantumProfile::select(header, config.profile);
+    if config.verbose {
+        status!(config, "Decoder profile: {}", profile.describe());
+        status!(config, "Decompressing...");
+    }
+
+    let Some(last_selected) = selected.iter().rposition(|&s| s) else {
+        return Ok(());
+    };
+    let mut decoder =
+        QuantumDecoder::new(compressed_data, header.table_size, &profile);
+    // The stream is solid: everything up to the last selected file is decoded
+    let mut progress = config.progress.then(|| {
+        let total = files[..=last_selected].iter().map(|f| f.size as u64).sum();
+        progress::Progress::new(total)
+    });
+    let mut data = Vec::new();
+    for (index, f) in files.iter().enumerate().take(last_selected + 1) {
+        data.clear();
+        let size = f.size as usize;
+        let start = decoder.bits_consumed();
+        let decoded = match progress.as_mut() {
+            Some(progress) => {
+                progress.start_file(index, &f.name, f.size);
+                let mut report = |bytes| progress.update(bytes);
+                decoder.decode_file(size, &mut data, Some(&mut report))
+            }
+            None => decoder.decode_file(size, &mut data, None),
+        };
+        // Past the end of the data, the decoder only sees zero padding: any
+        // error it runs into there is down to the missing data
+        let ends_early = || {
+            Failure::new(
+                FailureKind::Integrity,
+                format!(
+                    "Compressed data ends early in '{}' (archive truncated \
+                     or damaged)",
+                    f.name
+                ),
+            )
+        };
+        if let Err(e) = decoded {
+            return Err(if decoder.overran() {
+                ends_early()
+            } else {
+                Failure::new(
+                    FailureKind::Decode,
+                    format!("{} (in '{}')", e, f.name),
+                )
+            });
+        }
+        let packed_bits = decoder.bits_consumed() - start;
+        let checksum = if index < files.len() - 1 {
+            Some(decoder.read_checksum())
+        } else {
+            None
+        };
+        // The decoder pads a short stream with zeros and carries on, so a
+        // truncated or damaged archive shows as decoding past its end. The
+        // file that needed the missing data is not handed on.
+        if decoder.overran() {
+            return Err(ends_early());
+        }
+        // Whatever goes wrong after decoding is writing the file out
+        if selected[index] {
+            on_file(index, f, &data, checksum, packed_bits)
+                .map_err(|e| Failure::new(FailureKind::Io, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract or test the archive
+fn do_extract_or_test(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    target: &Target,
+    config: &Config,
+) -> Result<(), Failure> {
+    let total_output_size: usize = files
+        .iter()
+        .zip(selected)
+        .filter(|(_, &s)| s)
+        .map(|(f, _)| f.size as usize)
+        .sum();
+
+    if config.verbose || config.action == Action::Test {
+        status!(
+            config,
+            "Quantum {}.{:02} archive - {} file(s), table size {}",
+            header.major_version,
+            header.minor_version,
+            header.num_files,
+            header.table_size
+        );
+        status!(config, "Total decompressed size: {} bytes", total_output_size);
+        status!(config, "Compressed data size:    {} bytes", compressed_data.len());
+        status!(config);
+    }
+
+    if total_output_size == 0 && !config.pipe_header {
+        status!(config, "Archive contains no data to extract.");
+        return Ok(());
+    }
+
+    if config.action == Action::Pipe {
+        return pipe_entries(header, files, selected, compressed_data, config);
+    }
+
+    if config.action == Action::Test {
+        decode_entries(
+            header,
+            files,
+            selected,
+            compressed_data,
+            config,
+            |_, f, _, _, _| {
+                if config.unpaq {
+                    let line = legacy::entry_line("Testing", &f.name);
+                    status!(config, "{}  OK", line);
+                }
+                Ok(())
+            },
+        )?;
+        status!(
+            config,
+            "Archive integrity test PASSED ({} bytes decompressed \
+             successfully; checksums not verified).",
+            total_output_size
+        );
+        return Ok(());
+    }
+
+    let plans = plan_output_paths(files, selected, config);
+    let mut extraction = Extraction::new(&target.output_dir, config);
+    let result = decode_entries(
+        header,
+        files,
+        selected,
+        compressed_data,
+        config,
+        |index, f, data, _, _| extraction.extract_entry(f, &plans[index], data),
+    );
+
+    match result {
+        Ok(()) if !(config.all_or_nothing && extraction.failed > 0) => {
+            if let Err(e) = extraction.commit() {
+                extraction.rollback();
+                let message = format!("{}; nothing was written", e);
+                return Err(Failure::new(FailureKind::Io, message));
+            }
+            extraction.finish();
+            if extraction.failed > 0 {
+                return Err(Failure::new(
+                    FailureKind::Io,
+                    format!("{} file(s) could not be extracted", extraction.failed),
+                ));
+            }
+            Ok(())
+        }
+        Ok(()) => {
+            extraction.rollback();
+            Err(Failure::new(
+                FailureKind::Io,
+                format!(
+                    "{} file(s) could not be extracted, nothing was written",
+                    extraction.failed
+                ),
+            ))
+        }
+        Err(e) => {
+            extraction.rollback();
+            if config.all_or_nothing {
+                let message = format!("{}; nothing was written", e.message);
+                Err(Failure::new(e.kind, message))
+            } else {
+                extraction.finish();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Write the selected entries to standard output, back to back or each
+/// after a header line (--pipe-header)
+fn pipe_entries(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    config: &Config,
+) -> Result<(), Failure> {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    // A reader that goes away early (`| head`) is not an error
+    let mut closed = false;
+    let result = decode_entries(
+        header,
+        files,
+        selected,
+        compressed_data,
+        config,
+        |_, f, data, _, _| {
+            let written = if config.pipe_header {
+                writeln!(out, "==> {} ({} bytes) <==", f.name, f.size)
+                    .and_then(|_| out.write_all(data))
+            } else {
+                out.write_all(data)
+            };
+            written.map_err(|e| {
+                closed = e.kind() == std::io::ErrorKind::BrokenPipe;
+                format!("Cannot write to standard output: {}", e)
+            })
+        },
+    );
+    match result.and_then(|_| {
+        out.flush().map_err(|e| {
+            let message = format!("Cannot write to standard output: {}", e);
+            Failure::new(FailureKind::Io, message)
+        })
+    }) {
+        Err(_) if closed => Ok(()),
+        result => result,
+    }
+}
+
+/// Compare the selected entries with the files extraction would have
+/// written to the target directory (see compare.rs). Every mismatch is
+/// listed, identical files only without -q.
+fn do_compare(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    target: &Target,
+    config: &Config,
+    claimed: &compare::Claimed,
+) -> Result<(), Failure> {
+    let base = &target.output_dir;
+    let plans = plan_output_paths(files, selected, config);
+    // Every entry accounts for its file, selected or not, so that only
+    // files foreign to the archives are reported as extra
+    for (index, f) in files.iter().enumerate() {
+        let path = paths::sanitize(&f.name)
+            .ok()
+            .and_then(|safe| config.name_mapping.map(index, &safe));
+        if let Some(path) = path {
+            claimed.claim(&base.join(path), config.fold_case);
+        }
+        if let PlannedPath::Numbered(path) = &plans[index] {
+            claimed.claim(&base.join(path), config.fold_case);
+        }
+    }
+
+    status!(config, "Comparing with {}", base.display());
+    let (mut identical, mut differ, mut missing, mut mtime) = (0, 0, 0, 0);
+    let mut unreadable = 0;
+    let result = decode_entries(
+        header,
+        files,
+        selected,
+        compressed_data,
+        config,
+        |index, f, data, _, _| {
+            let rel = match &plans[index] {
+                PlannedPath::Write(path) | PlannedPath::Numbered(path) => path,
+                PlannedPath::Skip(reason) => {
+                    status!(config, "  {} ({})", f.name, reason);
+                    return Ok(());
+                }
+                // Already reported, and counted below
+                PlannedPath::Fail => return Ok(()),
+            };
+            let path = compare::find(base, rel, config.fold_case);
+            let archived = f.datetime().to_unix(config.timezone);
+            let outcome = compare::compare(path.as_deref(), data, archived);
+            match outcome {
+                compare::Outcome::Identical => identical += 1,
+                compare::Outcome::Differs { .. } => differ += 1,
+                compare::Outcome::Missing | compare::Outcome::NotAFile => {
+                    missing += 1
+                }
+                compare::Outcome::Mtime { .. } => mtime += 1,
+                compare::Outcome::Unreadable(_) => unreadable += 1,
+            }
+            if outcome.is_identical() {
+                status!(config, "  {}: {}", f.name, outcome.describe(f.size));
+            } else {
+                outln!("  {}: {}", f.name, outcome.describe(f.size));
+            }
+            Ok(())
+        },
+    );
+    let unnamed = plans
+        .iter()
+        .filter(|plan| matches!(plan, PlannedPath::Fail))
+        .count();
+
+    status!(
+        config,
+        "\nCompared {} file(s): {} identical, {} differ, {} missing, \
+         {} different mtime, {} unreadable.",
+        identical + differ + missing + mtime + unreadable,
+        identical,
+        differ,
+        missing,
+        mtime,
+        unreadable
+    );
+    result?;
+    // A file that could not be read was not compared at all
+    if unreadable > 0 {
+        return Err(Failure::new(
+            FailureKind::Io,
+            format!(
+                "{} file(s) in {} could not be read",
+                unreadable,
+                base.display()
+            ),
+        ));
+    }
+    let mismatches = differ + missing + mtime + unnamed;
+    if mismatches > 0 {
+        return Err(Failure::new(
+            FailureKind::Differences,
+            format!("{} file(s) do not match {}", mismatches, base.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Test the archive and write the result as a --format report: every
+/// selected entry with its hashes, and the overall result
+fn report_test(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    target: &Target,
+    config: &Config,
+) -> Result<(), Failure> {
+    use json::Value;
+    let mut report =
+        report::ArchiveReport::new(config.format, &target.label, header, None);
+    let mut tested = vec![false; files.len()];
+    let mut bytes = 0u64;
+    let result = decode_entries(
+        header,
+        files,
+        selected,
+        compressed_data,
+        config,
+        |index, f, data, checksum, _| {
+            let mut entry = report::entry_json(index, f, config.timezone);
+            entry.extend(report::tested_fields(data, checksum));
+            report.entry(entry);
+            tested[index] = true;
+            bytes += data.len() as u64;
+            Ok(())
+        },
+    );
+
+    // Selected entries the decoder never got to
+    for (index, f) in files.iter().enumerate() {
+        if selected[index] && !tested[index] {
+            let mut entry = report::entry_json(index, f, config.timezone);
+            entry.extend(vec![("status", Value::string("not_tested"))]);
+            report.entry(entry);
+        }
+    }
+    let mut test = Value::object(vec![
+        (
+            "status",
+            Value::string(if result.is_ok() { "passed" } else { "failed" }),
+        ),
+        ("bytes", Value::Int(bytes as i64)),
+    ]);
+    if let Err(e) = &result {
+        test.extend(vec![("error", Value::string(e.message.as_str()))]);
+    }
+    report.finish(Some(test));
+    result
+}
+
+/// Columns of the --format csv/tsv table, matching report_table's rows
+fn table_columns(config: &Config) -> Vec<&'static str> {
+    let mut columns = vec!["archive"];
+    if config.action == Action::Info {
+        columns.extend(["version", "table_size", "window_bytes", "comp_flags"]);
+    }
+    columns.extend(["index", "name", "size", "modified", "comment"]);
+    if config.hashes {
+        columns.extend(["crc32", "sha256"]);
+    }
+    columns
+}
+
+/// Write the selected entries as rows of the --format csv/tsv table. -i adds
+/// the archive's header fields to every row; --hashes decodes the archive to
+/// add content hashes.
+fn report_table(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    selected: &[bool],
+    compressed_data: Vec<u8>,
+    target: &Target,
+    config: &Config,
+) -> Result<(), Failure> {
+    let info = config.action == Action::Info;
+    let mut hashes: Vec<Option<(String, String)>> = vec![None; files.len()];
+    let mut result = Ok(());
+    if config.hashes {
+        result = decode_entries(
+            header,
+            files,
+            selected,
+            compressed_data,
+            config,
+            |index, _, data, _, _| {
+                hashes[index] = Some((
+                    format!("{:08x}", hash::crc32(data)),
+                    hash::hex(&hash::sha256(data)),
+                ));
+                Ok(())
+            },
+        );
+    }
+
+    for (index, f) in files.iter().enumerate() {
+        if !selected[index] {
+            continue;
+        }
+        let mut row = vec![target.label.clone()];
+        if info {
+            row.extend([
+                format!("{}.{:02}", header.major_version, header.minor_version),
+                header.table_size.to_string(),
+                (1u64 << header.table_size).to_string(),
+                header.comp_flags.to_string(),
+            ]);
+        }
+        row.extend([
+            index.to_string(),
+            f.name.clone(),
+            f.size.to_string(),
+            f.datetime().iso8601(config.timezone),
+            f.comment.clone(),
+        ]);
+        if config.hashes {
+            // Entries the decoder did not reach keep empty cells
+            let (crc, sha) = hashes[index].take().unwrap_or_default();
+            row.extend([crc, sha]);
+        }
+        report::table_row(config.format, &row);
+    }
+    result
+}
+
+/// State of one archive's extraction.
+///
+/// Every file is written to a temporary name next to its target and renamed
+/// into place once complete. In --all-or-nothing mode the renames are held
+/// back until the whole archive has decoded, so a bad archive leaves the
+/// output tree untouched.
+struct Extraction<'a> {
+    config: &'a Config,
+    base_dir: PathBuf,
+    /// Directories created here get the newest time of the files inside
+    created_dirs: Vec<PathBuf>,
+    written: Vec<(PathBuf, SystemTime)>,
+    /// Temporary file currently being written, removed if anything fails
+    current_temp: Option<PathBuf>,
+    /// Completed temporary files and their final paths, for all-or-nothing
+    pending: Vec<(PathBuf, PathBuf)>,
+    failed: usize,
+    overwritten: usize,
+    updated: usize,
+    skipped: usize,
+    renamed: usize,
+    not_extracted: usize,
+    extracted_bytes: u64,
+}
+
+impl<'a> Extraction<'a> {
+    fn new(base_dir: &Path, config: &'a Config) -> Self {
+        Extraction {
+            config,
+            base_dir: base_dir.to_path_buf(),
+            created_dirs: Vec::new(),
+            written: Vec::new(),
+            current_temp: None,
+            pending: Vec::new(),
+            failed: 0,
+            overwritten: 0,
+            updated: 0,
+            skipped: 0,
+            renamed: 0,
+            not_extracted: 0,
+            extracted_bytes: 0,
+        }
+    }
+
+    /// Write one decoded entry according to its plan and the policies for
+    /// existing files. Problems with a single entry are reported and counted;
+    /// only I/O errors that make further extraction pointless are returned.
+    fn extract_entry(
+        &mut self,
+        f: &QFileEntry,
+        plan: &PlannedPath,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let config = self.config;
+        let mut note = String::new();
+        let rel_path = match plan {
+            PlannedPath::Write(rel_path) => rel_path,
+            PlannedPath::Numbered(rel_path) => {
+                note = format!(
+                    " (duplicate, saved as {})",
+                    rel_path.file_name().unwrap().to_string_lossy()
+                );
+                rel_path
+            }
+            PlannedPath::Skip(reason) => {
+                status!(config, "  {} ({})", f.name, reason);
+                self.not_extracted += 1;
+                return Ok(());
+            }
+            PlannedPath::Fail => {
+                self.failed += 1;
+                return Ok(());
+            }
+        };
+        if let Err(e) = paths::check_no_symlinks(&self.base_dir, rel_path) {
+            errln!("Error: {}, '{}' skipped", e, f.name);
+            self.failed += 1;
+            return Ok(());
+        }
+        let mut file_path = self.base_dir.join(rel_path);
+
+        // Freshen/update decide by timestamp; otherwise apply the overwrite
+        // policy to files that already exist
+        let existing = fs::symlink_metadata(&file_path).ok();
+        match (config.update_mode, &existing) {
+            (UpdateMode::Freshen, None) => {
+                status!(config, "  {} (not on disk, skipped)", f.name);
+                self.skipped += 1;
+                return Ok(());
+            }
+            (UpdateMode::Freshen | UpdateMode::Update, Some(meta)) => {
+                if !is_newer_than(f, meta, config.timezone) {
+                    status!(config, "  {} (up to date, skipped)", f.name);
+                    self.skipped += 1;
+                    return Ok(());
+                }
+                note = " (updated)".to_string();
+                self.updated += 1;
+            }
+            (_, None) => {}
+            (UpdateMode::All, Some(_)) => match config.overwrite {
+                OverwritePolicy::Overwrite => {
+                    note = " (overwritten)".to_string();
+                    self.overwritten += 1;
+                }
+                OverwritePolicy::Skip => {
+                    status!(config, "  {} (skipped, exists)", f.name);
+                    self.skipped += 1;
+                    return Ok(());
+                }
+                OverwritePolicy::Rename => {
+                    file_path = paths::free_numbered_name(&file_path);
+                    note = format!(
+                        " (renamed to {})",
+                        file_path.file_name().unwrap().to_string_lossy()
+                    );
+                    self.renamed += 1;
+                }
+                OverwritePolicy::Fail => {
+                    errln!(
+                        "Error: {} already exists, '{}' not extracted",
+                        file_path.display(),
+                        f.name
+                    );
+                    self.failed += 1;
+                    return Ok(());
+                }
+            },
+        }
+
+        // Create parent directories if needed
+        if let Some(parent) = file_path.parent() {
+            if !parent.exists() {
+                let mut missing = parent;
+                while !missing.exists() && missing.starts_with(&self.base_dir) {
+                    self.created_dirs.push(missing.to_path_buf());
+                    match missing.parent() {
+                        Some(p) => missing = p,
+                        None => break,
+                    }
+                }
+                fs::create_dir_all(parent).map_err(|e| {
+                    format!(
+                        "Failed to create directory {}: {}",
+                        parent.display(),
+                        e
+                    )
+                })?;
+            }
+        }
+
+        // Write the file under a temporary name, with the archived time. A
+        // name already taken (left over from a crashed run) is never touched:
+        // the next one is tried.
+        let mtime = f.datetime().to_system_time(config.timezone);
+        let mut attempts = 0;
+        let temp_path = loop {
+            let temp_path = paths::temp_name(&file_path);
+            match write_new_file(&temp_path, data, mtime) {
+                Ok(()) => break temp_path,
+                Err(e)
+                    if e.kind() == ErrorKind::AlreadyExists && attempts < 100 =>
+                {
+                    attempts += 1;
+                }
+                Err(e) => {
+                    if e.kind() != ErrorKind::AlreadyExists {
+                        // Remove whatever part was written
+                        self.current_temp = Some(temp_path);
+                    }
+                    return Err(format!(
+                        "Failed to write file {}: {}",
+                        file_path.display(),
+                        e
+                    ));
+                }
+            }
+        };
+        self.current_temp = Some(temp_path.clone());
+
+        if config.all_or_nothing {
+            self.pending.push((temp_path, file_path.clone()));
+        } else {
+            fs::rename(&temp_path, &file_path).map_err(|e| {
+                format!("Failed to create file {}: {}", file_path.display(), e)
+            })?;
+        }
+        self.current_temp = None;
+        self.written.push((file_path.clone(), mtime));
+        self.extracted_bytes += f.size as u64;
+
+        if config.unpaq {
+            let line = legacy::entry_line("Extracting", &f.name);
+            status!(config, "{}{}", line, note);
+        } else if config.verbose {
+            let path = file_path.display();
+            status!(config, "  {} ({} bytes){}", path, f.size, note);
+        } else {
+            status!(config, "  {}{}", f.name, note);
+        }
+        Ok(())
+    }
+
+    /// Move files held back by --all-or-nothing into place. Files they
+    /// replace are first moved aside, so that if any rename fails, the ones
+    /// already done can be undone and the output tree is left as it was;
+    /// the temporary files stay pending for `rollback`.
+    fn commit(&mut self) -> Result<(), String> {
+        // Final paths placed so far, with the moved-aside file they replaced
+        let mut placed: Vec<(&Path, Option<PathBuf>)> = Vec::new();
+        let mut result = Ok(());
+        for (temp_path, file_path) in &self.pending {
+            // A directory in the way is left for the rename to fail on
+            let backup = paths::temp_name(file_path);
+            let replaced = if file_path.is_dir() {
+                None
+            } else {
+                match fs::rename(file_path, &backup) {
+                    Ok(()) => Some(backup),
+                    Err(e) if e.kind() == ErrorKind::NotFound => None,
+                    Err(e) => {
+                        result = Err(format!(
+                            "Failed to replace file {}: {}",
+                            file_path.display(),
+                            e
+                        ));
+                        break;
+                    }
+                }
+            };
+            if let Err(e) = fs::rename(temp_path, file_path) {
+                if let Some(backup) = &replaced {
+                    let _ = fs::rename(backup, file_path);
+                }
+                result = Err(format!(
+                    "Failed to create file {}: {}",
+                    file_path.display(),
+                    e
+                ));
+                break;
+            }
+            placed.push((file_path, replaced));
+        }
+        for (file_path, replaced) in placed.into_iter().rev() {
+            match replaced {
+                Some(backup) if result.is_ok() => {
+                    let _ = fs::remove_file(backup);
+                }
+                Some(backup) => {
+                    let _ = fs::rename(backup, file_path);
+                }
+                None if result.is_err() => {
+                    let _ = fs::remove_file(file_path);
+                }
+                None => {}
+            }
+        }
+        if result.is_ok() {
+            self.pending.clear();
+        }
+        result
+    }
+
+    /// Remove the partial file of a failed write and, in --all-or-nothing
+    /// mode, everything this extraction has created
+    fn rollback(&mut self) {
+        if let Some(temp_path) = self.current_temp.take() {
+            let _ = fs::remove_file(temp_path);
+        }
+        if !self.config.all_or_nothing {
+            return;
+        }
+        for (temp_path, _) in self.pending.drain(..) {
+            let _ = fs::remove_file(temp_path);
+        }
+        // Deepest first; only directories left empty are removed
+        self.created_dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in self.created_dirs.drain(..) {
+            let _ = fs::remove_dir(dir);
+        }
+        self.written.clear();
+        self.extracted_bytes = 0;
+    }
+
+    /// Stamp created directories and print the summary
+    fn finish(&self) {
+        for dir in &self.created_dirs {
+            let newest = self
+                .written
+                .iter()
+                .filter(|(path, _)| path.starts_with(dir))
+                .map(|&(_, mtime)| mtime)
+                .max();
+            if let Some(mtime) = newest {
+                if let Err(e) = set_dir_modified(dir, mtime) {
+                    errln!(
+                        "Warning: Cannot set time of {}: {}",
+                        dir.display(),
+                        e
+                    );
+                }
+            }
+        }
 
-    if config.action == Action::Test {
-        println!(
-            "Archive integrity test PASSED ({} bytes decompressed successfully).",
-            total_output_size
+        status!(
+            self.config,
+            "\nExtracted {} file(s), {} bytes total.",
+            self.written.len(),
+            self.extracted_bytes
         );
-        return Ok(());
+        let existing_counts: Vec<String> = [
+            (self.overwritten, "overwritten"),
+            (self.updated, "updated"),
+            (self.renamed, "renamed"),
+            (self.skipped, "skipped"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{} {}", n, what))
+        .collect();
+        if !existing_counts.is_empty() {
+            let counts = existing_counts.join(", ");
+            status!(self.config, "Existing files: {}.", counts);
+        }
+        if self.not_extracted > 0 {
+            let count = self.not_extracted;
+            status!(self.config, "Entries not extracted: {}.", count);
+        }
+    }
+}
+
+/// Create a new file with the given contents and modification time
+fn write_new_file(
+    path: &Path,
+    data: &[u8],
+    mtime: SystemTime,
+) -> std::io::Result<()> {
+    let mut out_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    out_file.write_all(data)?;
+    // Restore the archived modification time
+    if let Err(e) = out_file.set_modified(mtime) {
+        errln!("Warning: Cannot set time of {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Exit status when some archives of the run failed and others did not
+const EXIT_PARTIAL: i32 = 1;
+/// Exit status for an invalid command line
+const EXIT_USAGE: i32 = 2;
+
+/// Why an archive could not be processed
+#[derive(Clone, Copy, PartialEq)]
+enum FailureKind {
+    /// An input could not be read or an output file not written
+    Io,
+    /// Not a Quantum archive, a damaged header or an unsupported version
+    Header,
+    /// The compressed data holds something the decoder cannot follow
+    Decode,
+    /// Decoding needed more data than the archive holds: it is truncated
+    /// or damaged
+    Integrity,
+    /// The files on disk do not match the archive (--compare)
+    Differences,
+}
+
+impl FailureKind {
+    /// Exit status when every archive failed and this is the first reason
+    fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Io => 3,
+            FailureKind::Header => 4,
+            FailureKind::Decode => 5,
+            FailureKind::Integrity => 6,
+            FailureKind::Differences => 7,
+        }
+    }
+}
+
+/// An archive that could not be processed
+struct Failure {
+    kind: FailureKind,
+    message: String,
+}
+
+impl Failure {
+    fn new(kind: FailureKind, message: impl Into<String>) -> Failure {
+        Failure {
+            kind,
+            message: message.into(),
+        }
     }
+}
+
+/// One archive being processed: its name in messages and reports, and the
+/// directory its files are extracted to
+struct Target {
+    label: String,
+    output_dir: PathBuf,
+}
 
-    // Split decompressed data into individual files and write them
-    let base_dir = config
+/// Output directory for an archive called `name`. With --archive-dirs its
+/// files go to a subdirectory named after it; archives with the same name
+/// get GAME, GAME~1, ... in the order they are assigned.
+fn archive_dir(
+    name: &str,
+    config: &Config,
+    used: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let output_dir = config
         .output_dir
         .as_ref()
-        .map(|d| PathBuf::from(d))
+        .map(PathBuf::from)
         .unwrap_or_else(|| PathBuf::from("."));
+    if !config.archive_dirs {
+        return output_dir;
+    }
+    let file_name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => file_name,
+    };
+    let stem = match stem {
+        "" | "." | ".." => "archive",
+        "-" => "stdin",
+        _ => stem,
+    };
+    let mut dir = output_dir.join(stem);
+    let mut n = 0;
+    while !used.insert(dir.clone()) {
+        n += 1;
+        dir = output_dir.join(format!("{}~{}", stem, n));
+    }
+    dir
+}
 
-    let mut offset: usize = 0;
-    for f in files {
-        let end = offset + f.size as usize;
-        let file_data = &decompressed[offset..end];
-        offset = end;
+/// The output directories of one input's archives. With --archive-dirs they
+/// are assigned on the main thread for every input before any runs, in
+/// input order, so which archive gets GAME~1 does not depend on which
+/// worker (-j) gets there first.
+#[derive(Default)]
+struct InputPlan {
+    dirs: VecDeque<PathBuf>,
+    /// Standard input holding a disk image, read to find its archives
+    stdin_data: Option<Vec<u8>>,
+}
 
-        // Convert DOS path separators to native
-        let native_name = f.name.replace('\\', "/");
-        let file_path = if config.restore_dirs {
-            base_dir.join(&native_name)
+impl InputPlan {
+    /// Assign the directories of the archives in `path`. An input that
+    /// cannot be read gets none; reading it fails again when it runs.
+    fn new(path: &str, config: &Config, used: &mut HashSet<PathBuf>) -> Self {
+        let mut plan = InputPlan::default();
+        if !config.archive_dirs {
+            return plan;
+        }
+        let file_data;
+        let data: &[u8] = if path == "-" {
+            let mut input = std::io::stdin().lock();
+            let is_archive = !config.force_image
+                && input
+                    .fill_buf()
+                    .is_ok_and(|head| head.starts_with(&QTM_SIGNATURE));
+            let mut data = Vec::new();
+            if is_archive || input.read_to_end(&mut data).is_err() {
+                plan.dirs.push_back(archive_dir(path, config, used));
+                return plan;
+            }
+            plan.stdin_data = Some(data);
+            plan.stdin_data.as_deref().unwrap_or_default()
         } else {
-            // Strip directory components, keep only filename
-            let filename = Path::new(&native_name)
-                .file_name()
-                .unwrap_or_else(|| std::ffi::OsStr::new(&native_name));
-            base_dir.join(filename)
+            // Archives are recognised by their signature; only disk images
+            // are read in full here
+            let mut head = Vec::new();
+            let is_archive = !config.force_image
+                && File::open(path)
+                    .and_then(|f| f.take(2).read_to_end(&mut head))
+                    .is_ok_and(|_| head.starts_with(&QTM_SIGNATURE));
+            match fs::read(path) {
+                Ok(data) if !is_archive => file_data = data,
+                _ => {
+                    plan.dirs.push_back(archive_dir(path, config, used));
+                    return plan;
+                }
+            }
+            &file_data
         };
+        if !is_image(data, config) {
+            plan.dirs.push_back(archive_dir(path, config, used));
+        } else if let Ok(volumes) = fat::open_image(data) {
+            for (_, file) in image_archives(&volumes, config) {
+                plan.dirs.push_back(archive_dir(&file.path, config, used));
+            }
+        }
+        plan
+    }
 
-        // Create parent directories if needed
-        if let Some(parent) = file_path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent).map_err(|e| {
-                    format!(
-                        "Failed to create directory {}: {}",
-                        parent.display(),
-                        e
-                    )
+    /// The target of the next archive of the input, called `name`
+    fn target(&mut self, label: String, name: &str, config: &Config) -> Target {
+        let output_dir = self
+            .dirs
+            .pop_front()
+            .unwrap_or_else(|| archive_dir(name, config, &mut HashSet::new()));
+        Target { label, output_dir }
+    }
+}
+
+/// Results over the archives of the run, or of one input while workers
+/// (-j) process inputs separately
+#[derive(Default)]
+struct Batch {
+    archives: usize,
+    /// Labels of the archives (or inputs) that failed, and why
+    failed: Vec<(String, FailureKind)>,
+}
+
+impl Batch {
+    /// Add the results of another batch
+    fn merge(&mut self, other: Batch) {
+        self.archives += other.archives;
+        self.failed.extend(other.failed);
+    }
+
+    /// Count an archive's result, reporting a failure under its label
+    fn record(&mut self, label: &str, result: Result<(), Failure>) {
+        self.archives += 1;
+        if let Err(e) = result {
+            errln!("Error: {}: {}", label, e.message);
+            self.failed.push((label.to_string(), e.kind));
+        }
+    }
+
+    /// Exit status of the run: 0 if nothing failed, EXIT_PARTIAL if only
+    /// some archives did, otherwise that of the first failure
+    fn exit_code(&self) -> i32 {
+        match self.failed.first() {
+            None => 0,
+            Some(_) if self.failed.len() < self.archives => EXIT_PARTIAL,
+            Some(&(_, kind)) => kind.exit_code(),
+        }
+    }
+}
+
+/// Whether a command-line operand names an archive or disk image on disk,
+/// by .Q extension, Quantum signature or boot sector
+fn is_input_file(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if matches!(ext.as_str(), "q" | "img" | "ima") {
+        return true;
+    }
+    let mut head = Vec::new();
+    let read = File::open(path).and_then(|f| f.take(512).read_to_end(&mut head));
+    read.is_ok() && (looks_like_quantum(&head) || fat::looks_like_image(&head))
+}
+
+/// Whether an operand that names nothing on disk was meant as an input: it
+/// has an archive or image extension and no wildcards. Such an operand
+/// stays an input, so a misspelled archive name fails the run instead of
+/// becoming a pattern that matches nothing. A directory part alone does not
+/// count, since entry patterns such as DOCS/README.TXT have one too.
+fn looks_like_input_name(operand: &str) -> bool {
+    if operand.contains(['*', '?']) {
+        return false;
+    }
+    let ext = Path::new(operand)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    matches!(ext.as_str(), "q" | "img" | "ima")
+}
+
+/// Find archives below `dir`, by .Q extension (in any case) or signature,
+/// in a stable order, and with `images` disk images too. Symlinked
+/// directories are not followed.
+fn discover_archives(
+    dir: &Path,
+    images: bool,
+    found: &mut Vec<String>,
+) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read directory {}: {}", dir.display(), e))?;
+    let mut paths: Vec<(PathBuf, fs::FileType)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+        .collect();
+    paths.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, file_type) in paths {
+        if file_type.is_dir() {
+            discover_archives(&path, images, found)?;
+        } else if file_type.is_file() {
+            let has_q_ext = path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("q"));
+            let mut head = Vec::new();
+            if has_q_ext
+                || (images && is_input_file(&path))
+                || File::open(&path)
+                    .and_then(|f| f.take(8).read_to_end(&mut head))
+                    .is_ok_and(|_| looks_like_quantum(&head))
+            {
+                found.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a single archive and run the configured action on it
+fn run_archive(
+    archive_data: &[u8],
+    target: &Target,
+    config: &Config,
+    claimed: &compare::Claimed,
+) -> Result<(), Failure> {
+    let (header, files, data_offset) =
+        match parse_archive(archive_data, config.codepage) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                report::error(config.format, &target.label, &e);
+                return Err(Failure::new(FailureKind::Header, e));
+            }
+        };
+    let compressed_data = archive_data[data_offset..].to_vec();
+    let archive_size = archive_data.len();
+    run_parsed(
+        &header,
+        &files,
+        compressed_data,
+        archive_size,
+        target,
+        config,
+        claimed,
+    )
+}
+
+/// Run the configured action on an archive read from a stream. The header
+/// is parsed as it arrives; listings that decode nothing stop reading right
+/// after it.
+fn run_archive_stream(
+    input: &mut impl Read,
+    target: &Target,
+    config: &Config,
+    claimed: &compare::Claimed,
+) -> Result<(), Failure> {
+    let parsed = read_archive_header(input, config.codepage)
+        .map_err(|e| Failure::new(FailureKind::Header, e))
+        .and_then(|(header, files, data_offset)| {
+            let mut compressed_data = Vec::new();
+            if config.reads_data() {
+                input.read_to_end(&mut compressed_data).map_err(|e| {
+                    let message = format!("Cannot read archive: {}", e);
+                    Failure::new(FailureKind::Io, message)
                 })?;
             }
+            Ok((header, files, data_offset, compressed_data))
+        });
+    let (header, files, data_offset, compressed_data) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            report::error(config.format, &target.label, &e.message);
+            return Err(e);
         }
+    };
+    let archive_size = data_offset + compressed_data.len();
+    run_parsed(
+        &header,
+        &files,
+        compressed_data,
+        archive_size,
+        target,
+        config,
+        claimed,
+    )
+}
 
-        // Write the file
-        let mut out_file = File::create(&file_path).map_err(|e| {
-            format!("Failed to create file {}: {}", file_path.display(), e)
-        })?;
-        out_file.write_all(file_data).map_err(|e| {
-            format!("Failed to write file {}: {}", file_path.display(), e)
-        })?;
+/// Run the configured action on a parsed archive
+fn run_parsed(
+    header: &QArchiveHeader,
+    files: &[QFileEntry],
+    compressed_data: Vec<u8>,
+    archive_size: usize,
+    target: &Target,
+    config: &Config,
+    claimed: &compare::Claimed,
+) -> Result<(), Failure> {
+    let label = target.label.as_str();
+    // Listings only read the header, so version problems are just reported;
+    // decoding an unsupported version is refused unless forced.
+    if !matches!(config.action, Action::Info | Action::Scan) {
+        match header.compatibility() {
+            Ok(warnings) => {
+                for w in warnings {
+                    errln!("Warning: {}", w);
+                }
+            }
+            Err(e) if config.force || config.action == Action::List => {
+                errln!("Warning: {}", e);
+            }
+            Err(e) => {
+                let e = format!("{} (use --force to try anyway)", e);
+                report::error(config.format, label, &e);
+                return Err(Failure::new(FailureKind::Header, e));
+            }
+        }
+    }
 
-        if config.verbose {
-            println!("  {} ({} bytes)", file_path.display(), f.size);
-        } else {
-            println!("  {}", f.name);
+    let selected: Vec<bool> = files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            config.selection.matches(i, &f.name)
+                && config.selection.matches_metadata(
+                    f.datetime(),
+                    f.size,
+                    &f.comment,
+                )
+        })
+        .collect();
+    if config.action != Action::Info {
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        for pattern in config.selection.unmatched(&names) {
+            errln!("Warning: no entry matches '{}'", pattern);
         }
     }
 
-    println!(
-        "\nExtracted {} file(s), {} bytes total.",
-        files.len(),
-        total_output_size
-    );
+    let text = config.format == report::Format::Text;
+    match config.action {
+        Action::List | Action::Info if config.format.is_table() => {
+            // -i describes the whole archive, whatever the selection
+            let all = vec![true; files.len()];
+            let rows = if config.action == Action::Info { &all } else { &selected };
+            report_table(header, files, rows, compressed_data, target, config)?;
+        }
+        Action::List if text => {
+            let listed = do_list(
+                header,
+                files,
+                &selected,
+                compressed_data,
+                archive_size,
+                config,
+            );
+            report_collisions(files);
+            listed?;
+        }
+        Action::List => {
+            let mut report =
+                report::ArchiveReport::new(config.format, label, header, None);
+            for (index, f) in files.iter().enumerate() {
+                if selected[index] {
+                    report.entry(report::entry_json(index, f, config.timezone));
+                }
+            }
+            report.finish(None);
+            report_collisions(files);
+        }
+        Action::Info if text => {
+            do_info(header, files, archive_size, config);
+        }
+        Action::Scan => {
+            let total: u64 = files.iter().map(|f| f.size as u64).sum();
+            outln!(
+                "{}: Quantum {}.{:02}, {} file(s), {} bytes ({} packed)",
+                label,
+                header.major_version,
+                header.minor_version,
+                files.len(),
+                total,
+                archive_size
+            );
+        }
+        Action::Info => {
+            let info = info_json(header, files, archive_size, config);
+            let mut report =
+                report::ArchiveReport::new(config.format, label, header, Some(info));
+            for (index, f) in files.iter().enumerate() {
+                report.entry(report::entry_json(index, f, config.timezone));
+            }
+            report.finish(None);
+        }
+        Action::Test if !text => {
+            report_collisions(files);
+            report_test(header, files, &selected, compressed_data, target, config)?;
+        }
+        Action::Compare => {
+            report_collisions(files);
+            do_compare(
+                header,
+                files,
+                &selected,
+                compressed_data,
+                target,
+                config,
+                claimed,
+            )?;
+        }
+        Action::Extract | Action::Pipe | Action::Test => {
+            if config.action == Action::Test {
+                report_collisions(files);
+            }
+            do_extract_or_test(
+                header,
+                files,
+                &selected,
+                compressed_data,
+                target,
+                config,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether input data is a disk image rather than an archive
+fn is_image(data: &[u8], config: &Config) -> bool {
+    config.force_image
+        || (!data.starts_with(&QTM_SIGNATURE) && fat::looks_like_image(data))
+}
 
+/// The archives on the volumes of a disk image, matched by .Q extension or
+/// signature, depth first in directory order
+fn image_archives<'a>(
+    volumes: &'a [fat::FatVolume<'a>],
+    config: &Config,
+) -> Vec<(&'a fat::FatVolume<'a>, fat::FatFile)> {
+    let mut archives = Vec::new();
+    for volume in volumes {
+        for file in volume.files(config.codepage) {
+            let has_q_ext = file.path.to_ascii_uppercase().ends_with(".Q");
+            if has_q_ext || looks_like_quantum(&volume.read_head(&file, 8)) {
+                archives.push((volume, file));
+            }
+        }
+    }
+    archives
+}
+
+/// Find the Quantum archives inside a FAT disk image and run the configured
+/// action on each of them. Archives are matched by .Q extension or signature.
+fn run_image(
+    image: &[u8],
+    image_path: &str,
+    plan: &mut InputPlan,
+    config: &Config,
+    claimed: &compare::Claimed,
+    batch: &mut Batch,
+) -> Result<(), Failure> {
+    let volumes =
+        fat::open_image(image).map_err(|e| Failure::new(FailureKind::Header, e))?;
+
+    let archives = image_archives(&volumes, config);
+    let found = archives.len();
+    for (volume, file) in archives {
+        let label = match volume.partition {
+            Some(n) => format!("{}:p{}:{}", image_path, n, file.path),
+            None => format!("{}:{}", image_path, file.path),
+        };
+        let target = plan.target(label, &file.path, config);
+        let scan = config.action == Action::Scan;
+        if !scan {
+            status!(config, "==> {} ({} bytes) <==", target.label, file.size);
+        }
+
+        let result = match volume.read_file(&file) {
+            Ok(data) => run_archive(&data, &target, config, claimed),
+            Err(e) => {
+                report::error(config.format, &target.label, &e);
+                Err(Failure::new(FailureKind::Io, e))
+            }
+        };
+        batch.record(&target.label, result);
+        if !scan {
+            status!(config);
+        }
+    }
+
+    // An image without archives is a finding for scan, not a failure
+    if config.action == Action::Scan {
+        return Ok(());
+    }
+    if found == 0 {
+        return Err(Failure::new(
+            FailureKind::Header,
+            "No Quantum archives found in image",
+        ));
+    }
+    status!(config, "{} Quantum archive(s) found in image.", found);
     Ok(())
 }
 
+/// Run the configured action on one input: an archive or a disk image, from
+/// a file or from standard input
+fn run_input(
+    path: &str,
+    mut plan: InputPlan,
+    config: &Config,
+    claimed: &compare::Claimed,
+    batch: &mut Batch,
+) {
+    let label = if path == "-" { "<stdin>" } else { path };
+
+    // An archive on standard input is parsed as it arrives; anything else
+    // there (a disk image) needs random access and is read into memory
+    let mut stdin = None;
+    if path == "-" && plan.stdin_data.is_none() {
+        let mut input = std::io::stdin().lock();
+        let is_archive = !config.force_image
+            && input.fill_buf().is_ok_and(|head| head.starts_with(&QTM_SIGNATURE));
+        if is_archive {
+            let target = plan.target(label.to_string(), path, config);
+            let result =
+                run_archive_stream(&mut input, &target, config, claimed);
+            batch.record(label, result);
+            return;
+        }
+        stdin = Some(input);
+    }
+
+    // Read the entire archive (or disk image) into memory
+    let read = match stdin {
+        _ if plan.stdin_data.is_some() => Ok(plan.stdin_data.take().unwrap()),
+        Some(mut input) => {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data).map(|_| data)
+        }
+        None => fs::read(path),
+    };
+    let input_data = match read {
+        Ok(data) => data,
+        Err(e) => {
+            let e = format!("Cannot read input: {}", e);
+            report::error(config.format, label, &e);
+            batch.record(label, Err(Failure::new(FailureKind::Io, e)));
+            return;
+        }
+    };
+
+    if is_image(&input_data, config) {
+        let result =
+            run_image(&input_data, label, &mut plan, config, claimed, batch);
+        if let Err(e) = result {
+            report::error(config.format, label, &e.message);
+            batch.record(label, Err(e));
+        }
+    } else {
+        let target = plan.target(label.to_string(), path, config);
+        let result = run_archive(&input_data, &target, config, claimed);
+        batch.record(label, result);
+    }
+}
+
 fn main() {
-    let config = match parse_args() {
+    let args: Vec<String> = env::args().collect();
+    let config = match legacy::translate(args).and_then(parse_args) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Error: {}", e);
             eprintln!("Use -h for help.");
-            process::exit(1);
+            process::exit(EXIT_USAGE);
         }
     };
 
-    // Read the entire archive into memory
-    let archive_data = match fs::read(&config.archive_path) {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!(
-                "Error: Cannot read '{}': {}",
-                config.archive_path, e
-            );
-            process::exit(1);
+    // A named input that does not exist fails the run before anything is
+    // read or written
+    let mut missing = false;
+    for path in config.inputs.iter().filter(|p| *p != "-") {
+        if let Err(e) = fs::metadata(path) {
+            let e = format!("Cannot read input: {}", e);
+            report::error(config.format, path, &e);
+            errln!("Error: {}: {}", path, e);
+            missing = true;
         }
-    };
-
-    let archive_size = archive_data.len();
+    }
+    if missing {
+        report::finish_run(config.format);
+        process::exit(FailureKind::Io.exit_code());
+    }
 
-    // Parse the archive header and file entries
-    let (header, files, data_offset) = match parse_archive(&archive_data) {
-        Ok(result) => result,
-        Err(e) => {
+    let scan = config.action == Action::Scan;
+    // scan searches directories given as operands; -r does for every action
+    let operands =
+        config.inputs.iter().map(|p| (p, scan && Path::new(p).is_dir()));
+    let dirs = config.recurse.iter().map(|dir| (dir, true));
+    let mut inputs = Vec::new();
+    for (path, is_dir) in operands.chain(dirs) {
+        if !is_dir {
+            inputs.push(path.clone());
+            continue;
+        }
+        if let Err(e) = discover_archives(Path::new(path), scan, &mut inputs) {
             eprintln!("Error: {}", e);
-            process::exit(1);
+            process::exit(FailureKind::Io.exit_code());
+        }
+    }
+    // Finding nothing is an answer for scan, an error otherwise
+    if inputs.is_empty() && config.action != Action::Scan {
+        eprintln!("Error: No Quantum archives found");
+        process::exit(FailureKind::Header.exit_code());
+    }
+
+    if config.format.is_table() {
+        report::table_header(config.format, &table_columns(&config));
+    }
+
+    // With several inputs, each plain archive gets a header like the
+    // archives inside disk images do
+    let mut used_dirs = HashSet::new();
+    let plans: Vec<Mutex<InputPlan>> = inputs
+        .iter()
+        .map(|path| Mutex::new(InputPlan::new(path, &config, &mut used_dirs)))
+        .collect();
+    let mut batch = Batch::default();
+    // Files the archives account for, for --compare to report the rest
+    let claimed = compare::Claimed::default();
+    let run_one = |index: usize| {
+        let path = &inputs[index];
+        let plan = std::mem::take(&mut *plans[index].lock().unwrap());
+        let mut batch = Batch::default();
+        report::set_input(index);
+        let headers = inputs.len() > 1 && config.action != Action::Scan;
+        if headers {
+            status!(config, "==> {} <==", path);
+        }
+        run_input(path, plan, &config, &claimed, &mut batch);
+        if headers {
+            status!(config);
         }
+        batch
     };
 
-    match config.action {
-        Action::List => {
-            do_list(&header, &files);
+    if config.jobs <= 1 {
+        for index in 0..inputs.len() {
+            batch.merge(run_one(index));
         }
-        Action::Info => {
-            do_info(&header, &files, archive_size);
+    } else {
+        // Workers take the next input as they become free and hold back
+        // their output; it is printed here in input order, so the result
+        // reads the same as a run without -j
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        std::thread::scope(|scope| {
+            for _ in 0..config.jobs.min(inputs.len()) {
+                let sender = sender.clone();
+                let (next, run_one, count) = (&next, &run_one, inputs.len());
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= count {
+                        break;
+                    }
+                    let _ = sender.send((index, output::capture(|| run_one(index))));
+                });
+            }
+            drop(sender);
+
+            let mut pending = BTreeMap::new();
+            let mut printed = 0;
+            for (index, result) in receiver {
+                pending.insert(index, result);
+                while let Some((done, messages)) = pending.remove(&printed) {
+                    output::replay(messages);
+                    batch.merge(done);
+                    printed += 1;
+                }
+            }
+        });
+    }
+    report::finish_run(config.format);
+
+    // Files no archive accounts for are only known once all have been seen
+    let mut extras = 0;
+    if config.action == Action::Compare {
+        let root = config.output_dir.as_deref().unwrap_or(".");
+        let found = claimed.extras(Path::new(root), config.fold_case);
+        if !found.is_empty() {
+            outln!(
+                "Not in the archive(s): {} file(s) in {}",
+                found.len(),
+                root
+            );
+            for path in &found {
+                outln!("  {}: extra", path.display());
+            }
+            outln!();
         }
-        Action::Extract | Action::Test => {
-            let compressed_data = archive_data[data_offset..].to_vec();
+        extras = found.len();
+    }
 
-            if let Err(e) =
-                do_extract_or_test(&header, &files, compressed_data, &config)
-            {
-                eprintln!("Error: {}", e);
-                process::exit(1);
+    if config.action == Action::Scan {
+        let found = batch.archives - batch.failed.len();
+        status!(config, "{} Quantum archive(s) found.", found);
+    } else if inputs.len() > 1 {
+        status!(
+            config,
+            "{} archive(s) processed: {} OK, {} failed.",
+            batch.archives,
+            batch.archives - batch.failed.len(),
+            batch.failed.len()
+        );
+        for (label, _) in &batch.failed {
+            status!(config, "  failed: {}", label);
+        }
+    }
+    let mut exit_code = batch.exit_code();
+    if extras > 0 && exit_code == 0 {
+        exit_code = FailureKind::Differences.exit_code();
+    }
+    process::exit(exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode every file of an archive with `profile`
+    fn decode(archive: &[u8], profile: &QuantumProfile) -> Vec<Vec<u8>> {
+        let (header, files, offset) =
+            parse_archive(archive, CodePage::Cp437).unwrap();
+        let mut decoder = QuantumDecoder::new(
+            archive[offset..].to_vec(),
+            header.table_size,
+            profile,
+        );
+        let mut out = Vec::new();
+        for (index, f) in files.iter().enumerate() {
+            let mut data = Vec::new();
+            decoder.decode_file(f.size as usize, &mut data, None).unwrap();
+            if index < files.len() - 1 {
+                decoder.read_checksum();
             }
+            out.push(data);
+        }
+        assert!(!decoder.overran());
+        out
+    }
+
+    fn header(major_version: u8, minor_version: u8) -> QArchiveHeader {
+        QArchiveHeader {
+            major_version,
+            minor_version,
+            num_files: 0,
+            table_size: 10,
+            comp_flags: 1,
+        }
+    }
+
+    #[test]
+    fn named_profiles_are_pinned() {
+        let parameters = |p: &QuantumProfile| {
+            (
+                p.rescale_threshold,
+                p.freq_increment,
+                (p.initial_shift, p.shift_reset),
+                (p.model4_cap, p.model5_cap),
+                p.selector6_min_length,
+            )
+        };
+        let pinned: Vec<_> =
+            PROFILES.iter().map(|(name, p)| (*name, parameters(p))).collect();
+        assert_eq!(
+            pinned,
+            [
+                ("0.97", (3800, 8, (4, 50), (24, 36), 5)),
+                ("cab", (3800, 8, (4, 50), (24, 36), 5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_follows_the_header_version() {
+        assert!(QuantumProfile::for_version(0, 97) == Some(PROFILE_Q097));
+        assert!(QuantumProfile::for_version(0, 95).is_none());
+        assert!(QuantumProfile::for_version(1, 97).is_none());
+        assert!(QuantumProfile::select(&header(0, 97), None) == PROFILE_Q097);
+        // Unknown versions, decoded only with --force, use the 0.97 model
+        assert!(QuantumProfile::select(&header(0, 95), None) == PROFILE_Q097);
+        let custom = QuantumProfile::parse("0.97:threshold=4000").unwrap();
+        let chosen = QuantumProfile::select(&header(0, 95), Some(custom));
+        assert_eq!(chosen.rescale_threshold, 4000);
+    }
+
+    #[test]
+    fn profile_names_and_overrides() {
+        for name in ["0.97", "cab", "CAB"] {
+            let p = QuantumProfile::parse(name).unwrap();
+            assert_eq!(p.describe(), PROFILE_Q097.describe());
+        }
+        let p = QuantumProfile::parse("cab:threshold=4000,minlen=3").unwrap();
+        assert_eq!(p.name, "custom");
+        assert_eq!(p.rescale_threshold, 4000);
+        assert_eq!(p.selector6_min_length, 3);
+        assert_eq!(p.freq_increment, 8);
+
+        assert!(QuantumProfile::parse("0.95").is_err());
+        assert!(QuantumProfile::parse("0.97:threshold").is_err());
+        assert!(QuantumProfile::parse("0.97:threshold=0").is_err());
+        assert!(QuantumProfile::parse("0.97:model4=43").is_err());
+        assert!(QuantumProfile::parse("0.97:speed=1").is_err());
+    }
+
+    #[test]
+    fn only_097_is_supported() {
+        assert!(header(0, 97).compatibility().unwrap().is_empty());
+        assert!(header(0, 95).compatibility().is_err());
+        assert!(header(1, 0).compatibility().is_err());
+    }
+
+    #[test]
+    fn decodes_sample_archives() {
+        let hello = "Hello, World! This is a test of the Quantum compressor.\n";
+        let single =
+            decode(include_bytes!("../tests/test_single.q"), &PROFILE_Q097);
+        assert_eq!(single, [hello.as_bytes()]);
+
+        let multi =
+            decode(include_bytes!("../tests/test_multi.q"), &PROFILE_Q097);
+        assert_eq!(multi.len(), 3);
+        assert_eq!(multi[0], single[0]);
+        assert_eq!(multi[1], b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789\n");
+        let fox = "The quick brown fox jumps over the lazy dog. ".repeat(10);
+        assert_eq!(multi[2], format!("{}\n", fox).into_bytes());
+    }
+
+    #[test]
+    fn collisions() {
+        let names =
+            ["README.TXT", "A/B.TXT", "readme.txt", "A\\B.TXT", "README.TXT"];
+        assert_eq!(
+            collision_warnings(&names),
+            [
+                "duplicate name 'README.TXT' (entries 0, 4)",
+                "names differ only in case: 'README.TXT', 'readme.txt' \
+                 (entries 0, 2)",
+                "duplicate name 'A/B.TXT' (entries 1, 3)",
+            ]
+        );
+        assert!(collision_warnings(&["A.TXT", "B.TXT"]).is_empty());
+        assert_eq!(
+            collision_warnings(&["x.dat", "X.DAT"]),
+            ["names differ only in case: 'x.dat', 'X.DAT' (entries 0, 1)"]
+        );
+    }
+
+    #[test]
+    fn truncated_data_overruns() {
+        let archive = include_bytes!("../tests/test_multi.q");
+        let (header, files, offset) =
+            parse_archive(archive, CodePage::Cp437).unwrap();
+        let mut decoder = QuantumDecoder::new(
+            archive[offset..archive.len() - 2].to_vec(),
+            header.table_size,
+            &PROFILE_Q097,
+        );
+        let mut data = Vec::new();
+        for f in &files {
+            decoder.decode_file(f.size as usize, &mut data, None).unwrap();
+            decoder.read_checksum();
+        }
+        assert!(decoder.overran());
+    }
+
+    #[test]
+    fn discovers_archives_by_extension_and_signature() {
+        let base = std::env::temp_dir()
+            .join(format!("unquantum-discover-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("sub")).unwrap();
+        let archive = include_bytes!("../tests/test_single.q");
+        fs::write(base.join("A.Q"), archive).unwrap();
+        fs::write(base.join("sub/b.q"), b"not checked").unwrap();
+        fs::write(base.join("sub/GAME.DAT"), archive).unwrap();
+        fs::write(base.join("README.TXT"), b"DS but not an archive").unwrap();
+
+        let mut found = Vec::new();
+        discover_archives(&base, false, &mut found).unwrap();
+        let found: Vec<String> = found
+            .iter()
+            .map(|p| Path::new(p).strip_prefix(&base).unwrap().to_owned())
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(found, ["A.Q", "sub/GAME.DAT", "sub/b.q"]);
+
+        assert!(is_input_file(&base.join("sub/GAME.DAT")));
+        assert!(!is_input_file(&base.join("README.TXT")));
+        assert!(!is_input_file(&base.join("missing.q")));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_is_undone() {
+        let base = std::env::temp_dir()
+            .join(format!("unquantum-commit-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("C.TXT")).unwrap();
+        fs::write(base.join("A.TXT"), b"old").unwrap();
+        let args = ["unquantum", "--all-or-nothing", "GAME.Q"];
+        let config =
+            parse_args(args.iter().map(|a| a.to_string()).collect()).unwrap();
+        let listing = |dir: &Path| {
+            let mut names: Vec<String> = fs::read_dir(dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // C.TXT is a directory, so the last rename fails
+        let mut extraction = Extraction::new(&base, &config);
+        for name in ["A.TXT", "B.TXT", "C.TXT"] {
+            let temp_path = paths::temp_name(&base.join(name));
+            fs::write(&temp_path, b"new").unwrap();
+            extraction.pending.push((temp_path, base.join(name)));
+        }
+        assert!(extraction.commit().is_err());
+        assert_eq!(extraction.pending.len(), 3);
+        extraction.rollback();
+        assert_eq!(listing(&base), ["A.TXT", "C.TXT"]);
+        assert_eq!(fs::read(base.join("A.TXT")).unwrap(), b"old");
+
+        let mut extraction = Extraction::new(&base, &config);
+        for name in ["A.TXT", "B.TXT"] {
+            let temp_path = paths::temp_name(&base.join(name));
+            fs::write(&temp_path, b"new").unwrap();
+            extraction.pending.push((temp_path, base.join(name)));
+        }
+        extraction.commit().unwrap();
+        assert!(extraction.pending.is_empty());
+        assert_eq!(listing(&base), ["A.TXT", "B.TXT", "C.TXT"]);
+        assert_eq!(fs::read(base.join("A.TXT")).unwrap(), b"new");
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn listing_columns() {
+        let columns = ListColumn::parse_list("index, ratio,comment-length");
+        assert!(
+            columns
+                == Ok(vec![
+                    ListColumn::Index,
+                    ListColumn::Ratio,
+                    ListColumn::CommentLength
+                ])
+        );
+        assert!(ListColumn::parse_list("index,").is_err());
+        assert!(ListColumn::parse_list("crc").is_err());
+        assert!(ListColumn::Packed.decodes() && !ListColumn::Depth.decodes());
+        assert!(SortKey::parse("date") == Ok(SortKey::Date));
+        assert!(SortKey::parse("Date").is_err());
+
+        assert_eq!(ratio_cell(50, 200), "25.0%");
+        assert_eq!(ratio_cell(3, 1), "300.0%");
+        assert_eq!(ratio_cell(10, 0), "-");
+    }
+
+    #[test]
+    fn entry_fields() {
+        let entry = |name: &str| QFileEntry {
+            name: name.to_string(),
+            comment: String::new(),
+            name_raw: Vec::new(),
+            comment_raw: Vec::new(),
+            size: 0,
+            time: (13 << 11) | (45 << 5) | 10,
+            date: (14 << 9) | (6 << 5) | 15,
+        };
+        assert_eq!(entry("README.TXT").depth(), 0);
+        assert_eq!(entry("A\\B/C.TXT").depth(), 2);
+        let f = entry("X");
+        assert_eq!(f.date_string(DateFormat::Dmy), "15-06-1994");
+        assert_eq!(f.date_string(DateFormat::Iso), "1994-06-15");
+        assert_eq!(f.time_string(), "13:45:20");
+    }
+
+    #[test]
+    fn archive_like_operands() {
+        for input in ["GAME.Q", "disk1.IMG", "a.ima", "dir/GAME.Q"] {
+            assert!(looks_like_input_name(input), "{}", input);
+        }
+        let patterns =
+            ["README.TXT", "*.Q", "DOCS/*", "GAME?.Q", "q", "DOCS/README.TXT"];
+        for pattern in patterns {
+            assert!(!looks_like_input_name(pattern), "{}", pattern);
         }
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect();
+        let config =
+            parse_args(args(&["unquantum", "-l", "A.Q", "sub/x", "--", "B.Q"]))
+                .unwrap();
+        assert_eq!(config.inputs, ["A.Q"]);
+        assert_eq!(config.selection.include, ["sub/x", "B.Q"]);
     }
 }
@@ -0,0 +1,104 @@
+// UnQuantum - console output that can be held back
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Messages go through outln!/errln! instead of println!/eprintln!. Normally
+// they are printed at once; while a worker thread runs a job under
+// capture(), they are kept in order and printed later with replay(), so
+// that parallel jobs (-j) never interleave their output.
+//
+// A reader that stops early (`unquantum -l x.q | head`) ends the run quietly,
+// as far as standard output is concerned; other errors writing it are fatal.
+
+use std::cell::RefCell;
+use std::io::{self, ErrorKind, Write};
+use std::process;
+
+/// Which console stream a message belongs to
+pub enum Stream {
+    Out,
+    Err,
+}
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Vec<(Stream, String)>>> =
+        const { RefCell::new(None) };
+}
+
+/// Print a message, or keep it if this thread is capturing
+pub fn write(stream: Stream, text: String) {
+    let text = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(messages) => {
+            messages.push((stream, text));
+            None
+        }
+        None => Some((stream, text)),
+    });
+    // A progress line on the terminal would run into the message
+    if text.is_some() {
+        crate::progress::clear();
+    }
+    match text {
+        Some((Stream::Out, text)) => {
+            if let Err(e) = io::stdout().lock().write_all(text.as_bytes()) {
+                if e.kind() == ErrorKind::BrokenPipe {
+                    process::exit(0);
+                }
+                // Nowhere else to report it than standard error
+                let _ = writeln!(
+                    io::stderr(),
+                    "Error: Cannot write to standard output: {}",
+                    e
+                );
+                process::exit(crate::FailureKind::Io.exit_code());
+            }
+        }
+        Some((Stream::Err, text)) => {
+            // With standard error gone there is no one left to tell
+            let _ = io::stderr().lock().write_all(text.as_bytes());
+        }
+        None => {}
+    }
+}
+
+/// Run `job`, keeping everything it prints
+pub fn capture<T>(job: impl FnOnce() -> T) -> (T, Vec<(Stream, String)>) {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = job();
+    let messages = CAPTURED.with(|captured| captured.borrow_mut().take());
+    (result, messages.unwrap_or_default())
+}
+
+/// Print messages kept by capture(), in their original order
+pub fn replay(messages: Vec<(Stream, String)>) {
+    for (stream, text) in messages {
+        write(stream, text);
+    }
+}
+
+/// println! that respects capture()
+macro_rules! outln {
+    () => {
+        $crate::output::write($crate::output::Stream::Out, "\n".to_string())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(
+            $crate::output::Stream::Out,
+            format!("{}\n", format_args!($($arg)*)),
+        )
+    };
+}
+
+/// eprintln! that respects capture()
+macro_rules! errln {
+    () => {
+        $crate::output::write($crate::output::Stream::Err, "\n".to_string())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(
+            $crate::output::Stream::Err,
+            format!("{}\n", format_args!($($arg)*)),
+        )
+    };
+}
@@ -0,0 +1,568 @@
+// UnQuantum - safe mapping of archived DOS names to output paths
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Archived names are untrusted. A name such as `..\..\etc\x`, `\ABS\PATH` or
+// `C:\AUTOEXEC.BAT` must never place a file outside the output directory, and
+// extraction must not follow symlinks planted inside the output tree.
+//
+// After sanitising, every name goes through one mapping stage (NameMapping)
+// that applies the user's renaming options before anything is written.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An archived name reduced to plain relative path components
+pub struct SafeName {
+    pub components: Vec<String>,
+    /// What had to be changed, empty when the name was already safe
+    pub notes: Vec<&'static str>,
+}
+
+impl SafeName {
+    /// The relative path, joined with `/`
+    pub fn display(&self) -> String {
+        self.components.join("/")
+    }
+}
+
+/// Normalise an archived name: accept both `\` and `/` as separators, strip
+/// drive letters and leading separators, drop `.` components and resolve
+/// `..` without ever climbing above the output directory.
+pub fn sanitize(name: &str) -> Result<SafeName, String> {
+    let mut notes = Vec::new();
+    let mut rest = name;
+
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_alphabetic() {
+        rest = &rest[2..];
+        notes.push("drive letter removed");
+    }
+    let trimmed = rest.trim_start_matches(['\\', '/']);
+    if trimmed.len() != rest.len() {
+        notes.push("leading separator removed");
+        rest = trimmed;
+    }
+
+    let mut components: Vec<String> = Vec::new();
+    for part in rest.split(['\\', '/']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    notes.push("'..' above the output directory removed");
+                } else {
+                    notes.push("'..' resolved");
+                }
+            }
+            _ => components.push(part.to_string()),
+        }
+    }
+    notes.dedup();
+
+    if components.is_empty() {
+        return Err(format!("Archived name '{}' has no file name", name));
+    }
+    Ok(SafeName { components, notes })
+}
+
+/// Characters Windows does not allow in file names
+const WINDOWS_ILLEGAL: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Windows device names, reserved with or without an extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5",
+    "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5",
+    "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// One piece of a --name-template
+enum TemplatePiece {
+    Literal(String),
+    /// Entry index, zero-padded to the given width
+    Index(usize),
+    Name,
+    Stem,
+    Ext,
+}
+
+/// A parsed --name-template such as `{index:03}_{name}`
+pub struct NameTemplate {
+    pieces: Vec<TemplatePiece>,
+}
+
+impl NameTemplate {
+    /// Parse a template. Placeholders: {index}, {index:0N}, {name}, {stem}
+    /// and {ext}; `{{` and `}}` stand for literal braces. A template must
+    /// give every entry a name: `{ext}` alone is empty for `README`.
+    pub fn parse(template: &str) -> Result<NameTemplate, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => {
+                                return Err(format!(
+                                    "Unterminated field '{{{}' in template \
+                                     (write {{{{ for a literal brace)",
+                                    field
+                                ))
+                            }
+                        }
+                    }
+                    let piece = match field.split_once(':') {
+                        None if field == "index" => TemplatePiece::Index(0),
+                        None if field == "name" => TemplatePiece::Name,
+                        None if field == "stem" => TemplatePiece::Stem,
+                        None if field == "ext" => TemplatePiece::Ext,
+                        Some(("index", width)) => {
+                            TemplatePiece::Index(width.parse().map_err(|_| {
+                                format!("Invalid index width '{}'", width)
+                            })?)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Unknown template field '{{{}}}' (known: \
+                                 {{index}}, {{index:0N}}, {{name}}, {{stem}}, \
+                                 {{ext}})",
+                                field
+                            ))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(TemplatePiece::Literal(literal.clone()));
+                        literal.clear();
+                    }
+                    pieces.push(piece);
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(TemplatePiece::Literal(literal));
+        }
+        let names_every_entry = pieces.iter().any(|piece| match piece {
+            TemplatePiece::Literal(s) => s.chars().any(|c| c != '.'),
+            TemplatePiece::Ext => false,
+            _ => true,
+        });
+        if !names_every_entry {
+            return Err(format!(
+                "Template '{}' does not give every entry a name: use {{name}}, \
+                 {{stem}}, {{index}} or other text",
+                template
+            ));
+        }
+        Ok(NameTemplate { pieces })
+    }
+
+    /// Render the template for one entry's file name
+    fn render(&self, index: usize, name: &str) -> String {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+            _ => (name, ""),
+        };
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                TemplatePiece::Literal(s) => out.push_str(s),
+                TemplatePiece::Index(width) => {
+                    out.push_str(&format!("{:0width$}", index, width = *width))
+                }
+                TemplatePiece::Name => out.push_str(name),
+                TemplatePiece::Stem => out.push_str(stem),
+                TemplatePiece::Ext => out.push_str(ext),
+            }
+        }
+        // A template must not introduce new directory levels, nor name the
+        // directory itself or its parent (the stem of `...X` is `..`)
+        let out = out.replace(['/', '\\'], "_");
+        if out.chars().all(|c| c == '.') {
+            out.replace('.', "_")
+        } else {
+            out
+        }
+    }
+}
+
+/// The user's renaming options, applied to every sanitised name in order:
+/// strip leading directories, flatten, template, lowercase, host mapping.
+pub struct NameMapping {
+    /// Keep directory components (-d); otherwise only the file name is kept
+    pub restore_dirs: bool,
+    /// Leading directory components to drop (--strip-components)
+    pub strip_components: usize,
+    pub template: Option<NameTemplate>,
+    pub lowercase: bool,
+    /// Map names to what Windows accepts even on other hosts
+    pub portable: bool,
+}
+
+impl NameMapping {
+    /// Map a sanitised name to its output path, relative to the output
+    /// directory. None means the entry has no components left to extract.
+    pub fn map(&self, index: usize, safe: &SafeName) -> Option<PathBuf> {
+        let mut components: Vec<String> = if self.restore_dirs {
+            if safe.components.len() <= self.strip_components {
+                return None;
+            }
+            safe.components[self.strip_components..].to_vec()
+        } else {
+            vec![safe.components.last()?.clone()]
+        };
+
+        let last = components.len() - 1;
+        if let Some(template) = &self.template {
+            components[last] = template.render(index, &components[last]);
+        }
+        let windows_rules = self.portable || cfg!(windows);
+        for component in &mut components {
+            if self.lowercase {
+                *component = component.to_lowercase();
+            }
+            if windows_rules {
+                *component = windows_component(component);
+            }
+        }
+        Some(components.iter().collect())
+    }
+}
+
+/// Make a path component acceptable to Windows: replace illegal and control
+/// characters, trailing dots and spaces, and escape device names such as
+/// `CON` or `NUL.TXT` by appending `_` to the base name.
+fn windows_component(component: &str) -> String {
+    let mut out: String = component
+        .chars()
+        .map(|c| {
+            if WINDOWS_ILLEGAL.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let kept = out.trim_end_matches(['.', ' ']).len();
+    if kept < out.len() {
+        let replaced = out.len() - kept;
+        out.truncate(kept);
+        out.push_str(&"_".repeat(replaced));
+    }
+    let base_len = out.find('.').unwrap_or(out.len());
+    let base = out[..base_len].trim_end();
+    if WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(base)) {
+        out.insert(base.len(), '_');
+    }
+    out
+}
+
+/// Refuse to write through symlinks: check every existing component of
+/// `rel` below `base`, including the file itself. The base directory was
+/// chosen by the user and may be a symlink.
+pub fn check_no_symlinks(base: &Path, rel: &Path) -> Result<(), String> {
+    let mut path = PathBuf::from(base);
+    for component in rel.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                return Err(format!(
+                    "Refusing to write through symlink {}",
+                    path.display()
+                ));
+            }
+            Ok(_) => {}
+            // Nothing below a missing component can exist yet
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+/// `FOO.TXT` -> `FOO~n.TXT`, in the same directory
+pub fn numbered_name(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}~{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}~{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// The first `FOO~n.TXT` that does not exist yet
+pub fn free_numbered_name(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| numbered_name(path, n))
+        .find(|p| fs::symlink_metadata(p).is_err())
+        .unwrap()
+}
+
+/// Group the indices of names that collide, optionally ignoring case.
+/// Only groups with more than one member are returned, in the order their
+/// first member appears.
+pub fn find_collisions(
+    names: impl Iterator<Item = (usize, String)>,
+    fold_case: bool,
+) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    for (index, name) in names {
+        let key = if fold_case { name.to_uppercase() } else { name };
+        match by_key.get(&key) {
+            Some(&g) => groups[g].push(index),
+            None => {
+                by_key.insert(key, groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+/// Temporary files handed out so far by this process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Temporary name used while a file is written: `.FOO.TXT.<pid>.<n>.uqtmp`
+/// in the target directory, so the final rename never crosses file systems.
+/// Every call gives a new name, so workers (-j) writing the same file into
+/// one tree never share a temporary file.
+pub fn temp_name(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(
+        ".{}.{}.{}.uqtmp",
+        name,
+        std::process::id(),
+        n
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn mapping(dirs: bool, strip: usize, template: &str) -> NameMapping {
+        NameMapping {
+            restore_dirs: dirs,
+            strip_components: strip,
+            template: (!template.is_empty())
+                .then(|| NameTemplate::parse(template).unwrap()),
+            lowercase: false,
+            portable: false,
+        }
+    }
+
+    fn map(mapping: &NameMapping, index: usize, name: &str) -> Option<String> {
+        let safe = sanitize(name).unwrap();
+        let path = mapping.map(index, &safe)?;
+        Some(path.to_string_lossy().replace('\\', "/"))
+    }
+
+    #[test]
+    fn template_fields() {
+        let t = NameTemplate::parse("{index:03}_{stem}-{{{ext}}}").unwrap();
+        assert_eq!(t.render(7, "GAME.EXE"), "007_GAME-{EXE}");
+        assert_eq!(t.render(12, "README"), "012_README-{}");
+        let t = NameTemplate::parse("{index}.{name}").unwrap();
+        assert_eq!(t.render(3, ".PROFILE"), "3..PROFILE");
+    }
+
+    #[test]
+    fn template_errors() {
+        for bad in ["{index", "x{name", "{bogus}", "{index:x}"] {
+            assert!(NameTemplate::parse(bad).is_err(), "{}", bad);
+        }
+        // Templates that leave some entry without a name
+        for bad in ["", "{ext}", ".", "..", "{ext}.{ext}"] {
+            assert!(NameTemplate::parse(bad).is_err(), "{}", bad);
+        }
+        assert!(NameTemplate::parse("x{ext}").is_ok());
+    }
+
+    #[test]
+    fn template_cannot_leave_the_directory() {
+        let m = mapping(true, 0, "{stem}");
+        assert_eq!(map(&m, 0, "DIR\\...X").as_deref(), Some("DIR/__"));
+        let m = mapping(false, 0, "{name}/x\\y");
+        assert_eq!(map(&m, 0, "A.TXT").as_deref(), Some("A.TXT_x_y"));
+    }
+
+    #[test]
+    fn strip_components() {
+        let m = mapping(true, 1, "");
+        let kept = map(&m, 0, "GAME\\DATA\\X.DAT");
+        assert_eq!(kept.as_deref(), Some("DATA/X.DAT"));
+        assert_eq!(map(&m, 0, "GAME\\X.DAT").as_deref(), Some("X.DAT"));
+        assert_eq!(map(&m, 0, "X.DAT"), None);
+        let m = mapping(false, 0, "");
+        assert_eq!(map(&m, 0, "GAME\\DATA\\X.DAT").as_deref(), Some("X.DAT"));
+    }
+
+    fn safe(name: &str) -> (String, Vec<&'static str>) {
+        let safe = sanitize(name).unwrap();
+        (safe.display(), safe.notes)
+    }
+
+    #[test]
+    fn sanitize_keeps_safe_names() {
+        assert_eq!(safe("README.TXT"), ("README.TXT".to_string(), vec![]));
+        assert_eq!(safe("GAME\\DATA\\X.DAT").0, "GAME/DATA/X.DAT");
+        assert_eq!(safe("GAME/DATA/X.DAT").0, "GAME/DATA/X.DAT");
+        assert_eq!(safe("A\\.\\B\\\\C").0, "A/B/C");
+        // Only a whole `..` component climbs
+        assert_eq!(safe("...\\..X").0, ".../..X");
+    }
+
+    #[test]
+    fn sanitize_stays_inside_the_output_directory() {
+        let cases = [
+            ("..\\..\\etc\\passwd", "etc/passwd"),
+            ("../../../x", "x"),
+            ("\\ABS\\PATH.TXT", "ABS/PATH.TXT"),
+            ("/etc/passwd", "etc/passwd"),
+            ("C:\\AUTOEXEC.BAT", "AUTOEXEC.BAT"),
+            ("C:AUTOEXEC.BAT", "AUTOEXEC.BAT"),
+            ("c:/..\\..\\WIN.INI", "WIN.INI"),
+            ("A\\..\\..\\B", "B"),
+            ("A\\B\\..\\C", "A/C"),
+        ];
+        for (name, expected) in cases {
+            let (path, notes) = safe(name);
+            assert_eq!(path, expected, "{}", name);
+            assert!(!notes.is_empty(), "{}", name);
+            assert!(!path.split('/').any(|c| c == ".." || c.is_empty()));
+        }
+        let (_, notes) = safe("C:\\..\\X");
+        assert_eq!(
+            notes,
+            [
+                "drive letter removed",
+                "leading separator removed",
+                "'..' above the output directory removed"
+            ]
+        );
+    }
+
+    #[test]
+    fn sanitize_rejects_names_without_a_file() {
+        for bad in ["", "\\", "..", "C:", "A\\..", ".\\.", "C:\\..\\.."] {
+            assert!(sanitize(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_refused() {
+        let base = std::env::temp_dir()
+            .join(format!("unquantum-symlink-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("real")).unwrap();
+        std::os::unix::fs::symlink(base.join("real"), base.join("link"))
+            .unwrap();
+        std::os::unix::fs::symlink("/nonexistent", base.join("dangling"))
+            .unwrap();
+
+        assert!(check_no_symlinks(&base, Path::new("real/x.txt")).is_ok());
+        assert!(check_no_symlinks(&base, Path::new("new/dir/x.txt")).is_ok());
+        assert!(check_no_symlinks(&base, Path::new("link/x.txt")).is_err());
+        assert!(check_no_symlinks(&base, Path::new("dangling")).is_err());
+        // The base directory itself may be a symlink
+        assert!(check_no_symlinks(&base.join("link"), Path::new("x")).is_ok());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn collisions() {
+        let names = ["A.TXT", "B.TXT", "a.txt", "A.TXT", "C.TXT", "b.TXT"];
+        let named = || names.iter().map(|n| n.to_string()).enumerate();
+        assert_eq!(find_collisions(named(), false), [vec![0, 3]]);
+        assert_eq!(
+            find_collisions(named(), true),
+            [vec![0, 2, 3], vec![1, 5]]
+        );
+        let none = ["X", "Y"].iter().map(|n| n.to_string()).enumerate();
+        assert!(find_collisions(none, true).is_empty());
+    }
+
+    #[test]
+    fn numbered_names() {
+        let path = Path::new("out/DOCS/README.TXT");
+        assert_eq!(numbered_name(path, 1), Path::new("out/DOCS/README~1.TXT"));
+        let bare = numbered_name(Path::new("MAKEFILE"), 12);
+        assert_eq!(bare, Path::new("MAKEFILE~12"));
+    }
+
+    #[test]
+    fn portable_names() {
+        let cases = [
+            ("README.TXT", "README.TXT"),
+            ("CON", "CON_"),
+            ("nul.txt", "nul_.txt"),
+            ("LPT1.DOC.BAK", "LPT1_.DOC.BAK"),
+            ("CONFIG.SYS", "CONFIG.SYS"),
+            ("COM10", "COM10"),
+            ("A<B>:C.TXT", "A_B__C.TXT"),
+            ("WHAT?.*", "WHAT_._"),
+            ("TAB\tX", "TAB_X"),
+            ("NAME. ", "NAME__"),
+            ("AUX .TXT", "AUX_ .TXT"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(windows_component(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn lowercase_and_portable_mapping() {
+        let m = NameMapping {
+            lowercase: true,
+            portable: true,
+            ..mapping(true, 0, "{index}_{name}")
+        };
+        let mapped = map(&m, 4, "GAME\\CON.DAT");
+        assert_eq!(mapped.as_deref(), Some("game/4_con.dat"));
+        let mapped = map(&m, 0, "AUX\\X");
+        assert_eq!(mapped.as_deref(), Some("aux_/0_x"));
+    }
+
+    #[test]
+    fn temp_names_are_unique() {
+        let path = Path::new("out/README.TXT");
+        let names: Vec<PathBuf> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..100).map(|_| temp_name(path)).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+        let unique: HashSet<&PathBuf> = names.iter().collect();
+        assert_eq!(unique.len(), names.len());
+        for name in &names {
+            assert_eq!(name.parent(), Some(Path::new("out")));
+            let file = name.file_name().unwrap().to_string_lossy();
+            assert!(file.starts_with(".README.TXT."));
+            assert!(file.ends_with(".uqtmp"));
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// UnQuantum - progress display for long decodes (--progress)
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// One line on standard error, redrawn in place on a terminal:
+//
+//    43%  1.4 MiB / 3.2 MiB  #2 SUB\DATA.BIN  512.0 KiB/s  ETA 0:04
+//
+// The total is everything decoded to reach the last selected entry, since
+// the stream is solid. `#2` is the entry index as shown by -i. The decoder
+// reports every few kilobytes, but the line is redrawn at most ten times a
+// second, and not at all for decodes that finish within the first tenth of
+// a second. When standard error is not a terminal (a log file), a new line
+// is written every few seconds instead. Messages printed in between erase
+// the line first (see output.rs).
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Width of the line currently shown on the terminal, 0 if none
+static SHOWN: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Progress {
+    total: u64,
+    /// Bytes of the files before the current one
+    done: u64,
+    /// Bytes of the current file decoded so far
+    current: u64,
+    file_size: u64,
+    index: usize,
+    name: String,
+    started: Instant,
+    last_draw: Instant,
+    terminal: bool,
+}
+
+impl Progress {
+    /// Start reporting a decode of `total` bytes
+    pub fn new(total: u64) -> Progress {
+        let now = Instant::now();
+        Progress {
+            total,
+            done: 0,
+            current: 0,
+            file_size: 0,
+            index: 0,
+            name: String::new(),
+            started: now,
+            last_draw: now,
+            terminal: std::io::stderr().is_terminal(),
+        }
+    }
+
+    /// The previous file is complete and entry `index` is next
+    pub fn start_file(&mut self, index: usize, name: &str, size: u32) {
+        self.done += self.file_size;
+        self.current = 0;
+        self.file_size = size as u64;
+        self.index = index;
+        self.name = name.to_string();
+        self.draw();
+    }
+
+    /// `bytes` of the current file have been decoded
+    pub fn update(&mut self, bytes: usize) {
+        self.current = bytes as u64;
+        self.draw();
+    }
+
+    fn draw(&mut self) {
+        let interval = if self.terminal {
+            Duration::from_millis(100)
+        } else {
+            Duration::from_secs(5)
+        };
+        let now = Instant::now();
+        if now.duration_since(self.last_draw) < interval {
+            return;
+        }
+        self.last_draw = now;
+
+        let decoded = self.done + self.current;
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let rate = decoded as f64 / elapsed;
+        let eta = if decoded > 0 {
+            duration((self.total.saturating_sub(decoded)) as f64 / rate)
+        } else {
+            "?".to_string()
+        };
+        let line = format!(
+            "{:3}%  {} / {}  #{} {}  {}/s  ETA {}",
+            decoded * 100 / self.total.max(1),
+            size(decoded),
+            size(self.total),
+            self.index,
+            self.name,
+            size(rate as u64),
+            eta
+        );
+
+        if self.terminal {
+            // Pad over whatever is left of a longer previous line
+            let width = line.chars().count();
+            let previous = SHOWN.swap(width, Ordering::SeqCst);
+            let padding = " ".repeat(previous.saturating_sub(width));
+            eprint!("\r{}{}", line, padding);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        clear();
+    }
+}
+
+/// Erase the progress line, if one is on the terminal
+pub fn clear() {
+    let width = SHOWN.swap(0, Ordering::SeqCst);
+    if width > 0 {
+        eprint!("\r{}\r", " ".repeat(width));
+    }
+}
+
+/// A byte count in B, KiB or MiB
+fn size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Seconds as M:SS, or H:MM:SS from an hour up
+fn duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
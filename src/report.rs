@@ -0,0 +1,326 @@
+// UnQuantum - machine-readable reports (--format)
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Listings, archive information and test results can be written as JSON
+// instead of text. The schema is versioned by SCHEMA_VERSION: fields may be
+// added within a version, but never renamed or removed.
+//
+//   json    one document for the whole run, printed at the end:
+//           {"schema_version": 1, "archives": [ARCHIVE, ...]}
+//   ndjson  one record per line as soon as it is known, each carrying
+//           "schema_version", "type" ("archive", "entry", "test" or
+//           "error") and "archive"
+//   csv/tsv one table for the whole run, one row per entry, for listings
+//           and archive information
+//
+// The 16-bit checksums stored between files use an undocumented algorithm,
+// so they are reported but not verified. An entry passes a test when it
+// decodes to exactly its recorded size with the stream still in sync.
+
+use crate::dostime::TimeZone;
+use crate::hash;
+use crate::json::Value;
+use crate::{QArchiveHeader, QFileEntry};
+use std::cell::Cell;
+use std::sync::Mutex;
+
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// Output format for list, info and test
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!(
+                "Unknown format '{}' (known: text, json, ndjson, csv, tsv)",
+                name
+            )),
+        }
+    }
+
+    /// Whether this is one of the table formats
+    pub fn is_table(self) -> bool {
+        matches!(self, Format::Csv | Format::Tsv)
+    }
+}
+
+/// Archives of the current run, collected for the final JSON document and
+/// keyed by the input they came from
+static JSON_ARCHIVES: Mutex<Vec<(usize, Value)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Index of the input this thread is working on
+    static INPUT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Set the input whose archives this thread reports next, so that the JSON
+/// document lists them in command-line order even when workers (-j) finish
+/// out of order
+pub fn set_input(index: usize) {
+    INPUT.with(|input| input.set(index));
+}
+
+fn push_archive(archive: Value) {
+    let input = INPUT.with(|input| input.get());
+    JSON_ARCHIVES.lock().unwrap().push((input, archive));
+}
+
+/// Header fields
+pub fn header_json(header: &QArchiveHeader) -> Value {
+    Value::object(vec![
+        ("signature", Value::string("DS")),
+        ("major_version", Value::Int(header.major_version as i64)),
+        ("minor_version", Value::Int(header.minor_version as i64)),
+        (
+            "version",
+            Value::string(format!(
+                "{}.{:02}",
+                header.major_version, header.minor_version
+            )),
+        ),
+        ("num_files", Value::Int(header.num_files as i64)),
+        ("table_size", Value::Int(header.table_size as i64)),
+        ("window_bytes", Value::Int(1i64 << header.table_size)),
+        ("comp_flags", Value::Int(header.comp_flags as i64)),
+        ("compression_level", Value::Int(header.compression_level() as i64)),
+        (
+            "flags",
+            Value::Array(
+                header.flag_names().into_iter().map(Value::String).collect(),
+            ),
+        ),
+    ])
+}
+
+/// Stored fields of one entry
+pub fn entry_json(index: usize, f: &QFileEntry, tz: TimeZone) -> Value {
+    Value::object(vec![
+        ("index", Value::Int(index as i64)),
+        ("name", Value::string(f.name.as_str())),
+        ("name_bytes", Value::string(hash::hex(&f.name_raw))),
+        ("comment", Value::string(f.comment.as_str())),
+        ("comment_bytes", Value::string(hash::hex(&f.comment_raw))),
+        ("size", Value::Int(f.size as i64)),
+        ("modified", Value::string(f.datetime().iso8601(tz))),
+        ("dos_date", Value::Int(f.date as i64)),
+        ("dos_time", Value::Int(f.time as i64)),
+    ])
+}
+
+/// Test result fields for an entry that decoded correctly
+pub fn tested_fields(
+    data: &[u8],
+    checksum: Option<u16>,
+) -> Vec<(&'static str, Value)> {
+    let (stored, status) = match checksum {
+        Some(c) => (Value::string(format!("{:04x}", c)), "not_verified"),
+        None => (Value::Null, "none"),
+    };
+    vec![
+        ("status", Value::string("ok")),
+        ("stored_checksum", stored),
+        ("checksum_status", Value::string(status)),
+        ("crc32", Value::string(format!("{:08x}", hash::crc32(data)))),
+        ("sha256", Value::string(hash::hex(&hash::sha256(data)))),
+    ]
+}
+
+/// The report of one archive, written as its parts become known (ndjson)
+/// or as one object when finished (json)
+pub struct ArchiveReport {
+    format: Format,
+    label: String,
+    archive: Value,
+    entries: Vec<Value>,
+}
+
+impl ArchiveReport {
+    /// Start the report with the header and, for -i, the archive details
+    pub fn new(
+        format: Format,
+        label: &str,
+        header: &QArchiveHeader,
+        info: Option<Value>,
+    ) -> ArchiveReport {
+        let mut archive = Value::object(vec![
+            ("archive", Value::string(label)),
+            ("header", header_json(header)),
+        ]);
+        if let Some(info) = info {
+            archive.extend(vec![("info", info)]);
+        }
+        let report = ArchiveReport {
+            format,
+            label: label.to_string(),
+            archive,
+            entries: Vec::new(),
+        };
+        if format == Format::Ndjson {
+            report.write_record("archive", &report.archive);
+        }
+        report
+    }
+
+    pub fn entry(&mut self, entry: Value) {
+        match self.format {
+            Format::Ndjson => self.write_record("entry", &entry),
+            _ => self.entries.push(entry),
+        }
+    }
+
+    /// Finish the report, with the overall test result after -t
+    pub fn finish(mut self, test: Option<Value>) {
+        match self.format {
+            Format::Text | Format::Csv | Format::Tsv => {}
+            Format::Ndjson => {
+                if let Some(test) = test {
+                    self.write_record("test", &test);
+                }
+            }
+            Format::Json => {
+                let entries = std::mem::take(&mut self.entries);
+                self.archive.extend(vec![("entries", Value::Array(entries))]);
+                if let Some(test) = test {
+                    self.archive.extend(vec![("test", test)]);
+                }
+                push_archive(self.archive);
+            }
+        }
+    }
+
+    /// Print one ndjson line: the record's own fields after the common ones
+    fn write_record(&self, kind: &'static str, value: &Value) {
+        let mut record = record_start(kind, &self.label);
+        if let Value::Object(fields) = value {
+            for (key, field) in fields {
+                if *key != "archive" {
+                    record.extend(vec![(*key, field.clone())]);
+                }
+            }
+        }
+        outln!("{}", record.to_compact());
+    }
+}
+
+/// Record an archive that could not be read or decoded at all
+pub fn error(format: Format, label: &str, message: &str) {
+    match format {
+        Format::Text | Format::Csv | Format::Tsv => {}
+        Format::Ndjson => {
+            let mut record = record_start("error", label);
+            record.extend(vec![("error", Value::string(message))]);
+            outln!("{}", record.to_compact());
+        }
+        Format::Json => {
+            push_archive(Value::object(vec![
+                ("archive", Value::string(label)),
+                ("error", Value::string(message)),
+            ]));
+        }
+    }
+}
+
+/// Print the JSON document once every archive has been processed
+pub fn finish_run(format: Format) {
+    if format == Format::Json {
+        let mut archives = std::mem::take(&mut *JSON_ARCHIVES.lock().unwrap());
+        // Stable, so archives of the same input keep their order
+        archives.sort_by_key(|(input, _)| *input);
+        let archives = archives.into_iter().map(|(_, archive)| archive).collect();
+        let document = Value::object(vec![
+            ("schema_version", Value::Int(SCHEMA_VERSION)),
+            ("archives", Value::Array(archives)),
+        ]);
+        outln!("{}", document.to_pretty());
+    }
+}
+
+fn record_start(kind: &'static str, label: &str) -> Value {
+    Value::object(vec![
+        ("schema_version", Value::Int(SCHEMA_VERSION)),
+        ("type", Value::string(kind)),
+        ("archive", Value::string(label)),
+    ])
+}
+
+/// Print the header row of the table, once at the start of the run
+pub fn table_header(format: Format, columns: &[&str]) {
+    let cells: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+    table_row(format, &cells);
+}
+
+/// Print one row of the table
+pub fn table_row(format: Format, cells: &[String]) {
+    let (separator, escape): (&str, fn(&str) -> String) = match format {
+        Format::Tsv => ("\t", tsv_field),
+        _ => (",", csv_field),
+    };
+    let fields: Vec<String> = cells.iter().map(|c| escape(c)).collect();
+    outln!("{}", fields.join(separator));
+}
+
+/// RFC 4180: quote fields holding separators, quotes, line breaks or
+/// surrounding spaces, doubling any quotes inside
+fn csv_field(value: &str) -> String {
+    let needs_quotes = value.contains([',', '"', '\n', '\r'])
+        || value.starts_with(' ')
+        || value.ends_with(' ');
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// TSV cannot quote, so tabs and line breaks inside a field become spaces
+fn tsv_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(csv_field("README.TXT"), "README.TXT");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_field(" padded "), "\" padded \"");
+        assert_eq!(csv_field("in side"), "in side");
+        assert_eq!(csv_field("DOCS\\A.TXT"), "DOCS\\A.TXT");
+    }
+
+    #[test]
+    fn tsv_fields() {
+        assert_eq!(tsv_field("a\tb\nc\rd"), "a b c d");
+        assert_eq!(tsv_field("a,\"b\""), "a,\"b\"");
+    }
+
+    #[test]
+    fn formats() {
+        assert!(Format::parse("csv").unwrap().is_table());
+        assert!(Format::parse("tsv").unwrap().is_table());
+        assert!(!Format::parse("ndjson").unwrap().is_table());
+        assert!(Format::parse("CSV").is_err());
+        assert!(Format::parse("xml").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+// UnQuantum - choosing which archive entries to process
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// Entries are chosen by DOS-style wildcards (`*`, `?`), matched without
+// regard to case as DOS did, and by index ranges as shown by `-i`. A pattern
+// without a separator is matched against the file name alone, so `*.TXT`
+// finds text files in every directory; `DOCS\*` is matched against the
+// whole archived path. `/` and `\` are interchangeable.
+//
+// Entries can further be narrowed by their stored metadata: DOS date, size
+// and comment. Comments are searched for a substring or with a small regular
+// expression subset (Pattern below), both ignoring case.
+
+use crate::dostime::DosDateTime;
+use std::fs;
+
+/// The entries to process. With no patterns and no ranges, everything is
+/// selected; exclusions always win.
+#[derive(Default)]
+pub struct Selection {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Inclusive index ranges (--index)
+    pub ranges: Vec<(usize, usize)>,
+    /// Only entries dated strictly after this (--newer-than)
+    pub newer_than: Option<DosDateTime>,
+    /// Only entries dated strictly before this (--older-than)
+    pub older_than: Option<DosDateTime>,
+    /// Inclusive size bounds in bytes (--min-size, --max-size)
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Lower-cased substring the comment must contain (--comment)
+    pub comment: Option<String>,
+    pub comment_regex: Option<Pattern>,
+}
+
+impl Selection {
+    /// Add index ranges such as `2-5`, `7`, `10-` or `1,3-4`
+    pub fn add_ranges(&mut self, spec: &str) -> Result<(), String> {
+        for part in spec.split(',') {
+            let parse = |s: &str| {
+                s.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid index range '{}'", part))
+            };
+            let range = match part.split_once('-') {
+                Some((from, "")) => (parse(from)?, usize::MAX),
+                Some((from, to)) => (parse(from)?, parse(to)?),
+                None => (parse(part)?, parse(part)?),
+            };
+            if range.0 > range.1 {
+                return Err(format!("Invalid index range '{}'", part));
+            }
+            self.ranges.push(range);
+        }
+        Ok(())
+    }
+
+    /// Whether an entry is selected
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        if self.exclude.iter().any(|p| name_matches(p, name)) {
+            return false;
+        }
+        if self.include.is_empty() && self.ranges.is_empty() {
+            return true;
+        }
+        self.ranges.iter().any(|&(from, to)| (from..=to).contains(&index))
+            || self.include.iter().any(|p| name_matches(p, name))
+    }
+
+    /// Whether an entry's stored metadata passes the date, size and comment
+    /// filters
+    pub fn matches_metadata(
+        &self,
+        datetime: DosDateTime,
+        size: u32,
+        comment: &str,
+    ) -> bool {
+        let size = size as u64;
+        self.newer_than.is_none_or(|t| datetime > t)
+            && self.older_than.is_none_or(|t| datetime < t)
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self
+                .comment
+                .as_ref()
+                .is_none_or(|c| comment.to_lowercase().contains(c.as_str()))
+            && self.comment_regex.as_ref().is_none_or(|re| re.is_match(comment))
+    }
+
+    /// Include patterns that match none of the given names
+    pub fn unmatched<'a>(&'a self, names: &[&str]) -> Vec<&'a str> {
+        self.include
+            .iter()
+            .filter(|p| !names.iter().any(|n| name_matches(p, n)))
+            .map(|p| p.as_str())
+            .collect()
+    }
+}
+
+/// Parse a size such as `1500`, `64K` or `2M` (K and M are 1024-based)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1024),
+        Some((i, 'M' | 'm')) => (&s[..i], 1024 * 1024),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("Invalid size '{}'", s))
+}
+
+/// Read patterns from a list file: one per line, blank lines and lines
+/// starting with `#` or `;` are ignored
+pub fn read_list_file(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read list file {}: {}", path, e))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with(['#', ';']))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Match an archived name against one pattern
+fn name_matches(pattern: &str, name: &str) -> bool {
+    let pattern = normalize(pattern);
+    let name = normalize(name);
+    if pattern.contains(&'\\') {
+        glob_match(&pattern, &name)
+    } else {
+        let file_name = name.rsplit(|&c| c == '\\').next().unwrap_or(&name);
+        glob_match(&pattern, file_name)
+    }
+}
+
+fn normalize(s: &str) -> Vec<char> {
+    s.trim_start_matches(['\\', '/'])
+        .chars()
+        .map(|c| if c == '/' { '\\' } else { c })
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// Wildcard match: `*` matches any run of characters, `?` exactly one
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    p = bp;
+                    n = bn + 1;
+                    backtrack = Some((bp, bn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// ============================================================================
+// Comment regular expressions
+// ============================================================================
+
+/// What one regular expression element matches
+enum Atom {
+    Any,
+    Char(char),
+    /// Inclusive character ranges, optionally negated
+    Class(bool, Vec<(char, char)>),
+}
+
+/// How often an element may repeat
+#[derive(Clone, Copy, PartialEq)]
+enum Repeat {
+    Once,
+    /// `?`
+    Optional,
+    /// `*`
+    Any,
+    /// `+`
+    AtLeastOnce,
+}
+
+/// A small regular expression subset for --comment-regex: literals, `.`,
+/// classes such as `[a-z0-9]` or `[^,]`, the repeats `*`, `+` and `?`, the
+/// anchors `^` and `$`, and `\` to escape a special character. Matching
+/// ignores case and finds the expression anywhere in the text unless it is
+/// anchored.
+pub struct Pattern {
+    elements: Vec<(Atom, Repeat)>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+impl Pattern {
+    pub fn parse(re: &str) -> Result<Pattern, String> {
+        let err = |what: &str| format!("Invalid --comment-regex '{}': {}", re, what);
+        let mut chars = re.to_lowercase().chars().collect::<Vec<_>>().into_iter();
+        let mut elements: Vec<(Atom, Repeat)> = Vec::new();
+        let mut anchored_start = false;
+        let mut anchored_end = false;
+        let mut first = true;
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '^' if first => {
+                    anchored_start = true;
+                    first = false;
+                    continue;
+                }
+                '$' if chars.as_slice().is_empty() => {
+                    anchored_end = true;
+                    continue;
+                }
+                '.' => Atom::Any,
+                '\\' => Atom::Char(chars.next().ok_or_else(|| err("trailing '\\'"))?),
+                '[' => {
+                    let negated = chars.as_slice().first() == Some(&'^');
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::new();
+                    loop {
+                        let c = match chars.next() {
+                            None => return Err(err("unterminated '['")),
+                            Some(']') if !ranges.is_empty() => break,
+                            Some('\\') => chars.next().ok_or_else(|| err("trailing '\\'"))?,
+                            Some(c) => c,
+                        };
+                        let rest = chars.as_slice();
+                        if rest.len() >= 2 && rest[0] == '-' && rest[1] != ']' {
+                            let end = rest[1];
+                            chars.next();
+                            chars.next();
+                            if end < c {
+                                return Err(err("reversed range in '[...]'"));
+                            }
+                            ranges.push((c, end));
+                        } else {
+                            ranges.push((c, c));
+                        }
+                    }
+                    Atom::Class(negated, ranges)
+                }
+                '*' | '+' | '?' => {
+                    return Err(err("repeat without anything to repeat"));
+                }
+                c => Atom::Char(c),
+            };
+            first = false;
+            let repeat = match chars.as_slice().first() {
+                Some('?') => Repeat::Optional,
+                Some('*') => Repeat::Any,
+                Some('+') => Repeat::AtLeastOnce,
+                _ => Repeat::Once,
+            };
+            if repeat != Repeat::Once {
+                chars.next();
+            }
+            elements.push((atom, repeat));
+        }
+        Ok(Pattern { elements, anchored_start, anchored_end })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.to_lowercase().chars().collect();
+        if self.anchored_start {
+            return self.match_here(0, &text);
+        }
+        (0..=text.len()).any(|start| self.match_here(0, &text[start..]))
+    }
+
+    /// Match elements[e..] at the start of text, backtracking over repeats
+    fn match_here(&self, e: usize, text: &[char]) -> bool {
+        let Some((atom, repeat)) = self.elements.get(e) else {
+            return !self.anchored_end || text.is_empty();
+        };
+        let (min, max) = match repeat {
+            Repeat::Once => (1, 1),
+            Repeat::Optional => (0, 1),
+            Repeat::Any => (0, usize::MAX),
+            Repeat::AtLeastOnce => (1, usize::MAX),
+        };
+        let available = text.iter().take_while(|&&c| atom.matches(c)).count();
+        if available < min {
+            return false;
+        }
+        (min..=available.min(max))
+            .rev()
+            .any(|n| self.match_here(e + 1, &text[n..]))
+    }
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            Atom::Char(expected) => c == *expected,
+            Atom::Class(negated, ranges) => {
+                ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        glob_match(&normalize(pattern), &normalize(name))
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob("*.TXT", "README.TXT"));
+        assert!(glob("*.txt", "readme.TXT"));
+        assert!(glob("READ??.*", "README.DOC"));
+        assert!(!glob("READ??.*", "READ.DOC"));
+        assert!(glob("*", ""));
+        assert!(glob("*A*B*", "xxAyyBzz"));
+        assert!(!glob("*A*B", "xxAyyBzz"));
+        assert!(glob("**.Q", "GAME.Q"));
+        assert!(!glob("?", ""));
+        assert!(!glob("", "A"));
+        // No catastrophic backtracking on repeated stars
+        let name = "A".repeat(64);
+        assert!(!glob("*A*A*A*A*A*A*A*A*B", &name));
+    }
+
+    #[test]
+    fn patterns_and_paths() {
+        // Without a separator only the file name is matched
+        assert!(name_matches("*.TXT", "DOCS\\README.TXT"));
+        assert!(!name_matches("DOCS", "DOCS\\README.TXT"));
+        // With one, the whole path; both separators and a leading one work
+        assert!(name_matches("DOCS\\*", "DOCS\\README.TXT"));
+        assert!(name_matches("/docs/*.txt", "DOCS\\README.TXT"));
+        assert!(name_matches("DOCS/*", "DOCS/SUB/X.TXT"));
+        assert!(!name_matches("DOCS\\*", "README.TXT"));
+    }
+
+    #[test]
+    fn selection() {
+        let mut s = Selection::default();
+        assert!(s.matches(5, "ANY.DAT"));
+        s.add_ranges("1,3-4,10-").unwrap();
+        s.include.push("*.TXT".to_string());
+        s.exclude.push("SECRET.*".to_string());
+        let picked: Vec<usize> = (0..12)
+            .filter(|&i| s.matches(i, if i == 6 { "A.TXT" } else { "B.DAT" }))
+            .collect();
+        assert_eq!(picked, [1, 3, 4, 6, 10, 11]);
+        assert!(!s.matches(3, "SECRET.TXT"));
+        assert_eq!(s.unmatched(&["A.TXT"]), Vec::<&str>::new());
+        assert_eq!(s.unmatched(&["B.DAT"]), ["*.TXT"]);
+
+        for bad in ["", "x", "5-2", "-3", "1,,2", "1-2-3"] {
+            assert!(Selection::default().add_ranges(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size("64K"), Ok(65536));
+        assert_eq!(parse_size("2m"), Ok(2 * 1024 * 1024));
+        for bad in ["", "K", "-1", "1.5M", "10G", "99999999999999999999M"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn comment_regex() {
+        let re = |p: &str| Pattern::parse(p).unwrap();
+        assert!(re("demo").is_match("Shareware DEMO version"));
+        assert!(re("^v[0-9]+\\.[0-9]").is_match("V1.2 release"));
+        assert!(!re("^v[0-9]+\\.[0-9]").is_match("see v1.2"));
+        assert!(re("level ?[^a-z]$").is_match("LEVEL 3"));
+        assert!(!re("level ?[^a-z]$").is_match("level x"));
+        assert!(re("a.*z").is_match("abcz"));
+        assert!(re("colou?r").is_match("color"));
+        assert!(re("[]x]").is_match("]"));
+        assert!(re("a$b").is_match("a$b"));
+        assert!(re("").is_match(""));
+        for bad in ["*a", "a\\", "[a-", "[z-a]", "a++"] {
+            assert!(Pattern::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn metadata_filters() {
+        let date = |s| DosDateTime::parse(s).unwrap();
+        let s = Selection {
+            newer_than: Some(date("1994-01-01")),
+            older_than: Some(date("1995-01-01")),
+            min_size: Some(10),
+            max_size: Some(100),
+            comment: Some("demo".to_string()),
+            ..Default::default()
+        };
+        let day = date("1994-06-15");
+        assert!(s.matches_metadata(day, 50, "Game DEMO"));
+        assert!(!s.matches_metadata(date("1994-01-01"), 50, "demo"));
+        assert!(!s.matches_metadata(date("1995-01-01"), 50, "demo"));
+        assert!(s.matches_metadata(day, 10, "demo"));
+        assert!(s.matches_metadata(day, 100, "demo"));
+        assert!(!s.matches_metadata(day, 9, "demo"));
+        assert!(!s.matches_metadata(day, 101, "demo"));
+        assert!(!s.matches_metadata(day, 50, "full version"));
+    }
+}
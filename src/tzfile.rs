@@ -0,0 +1,561 @@
+// UnQuantum - local time zone rules
+//
+// Copyright (c) 2026 David Carrero Fernandez-Baillo
+// License: MIT (see LICENSE file)
+//
+// DOS timestamps are local times, so turning one into a Unix time needs the
+// offset from UTC that applied on that date, DST included. Unix systems keep
+// the rules in TZif files (RFC 8536) under /usr/share/zoneinfo, picked by the
+// TZ variable or /etc/localtime; TZ may instead hold a POSIX rule such as
+// `CET-1CEST,M3.5.0,M10.5.0/3`. Both are read here, without the C library.
+// Leap second records in "right/" zones are ignored.
+
+use crate::dostime::days_from_civil;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+/// Where zone names in TZ are looked up, after $TZDIR
+const ZONEINFO_DIRS: [&str; 3] =
+    ["/usr/share/zoneinfo", "/usr/lib/zoneinfo", "/usr/share/lib/zoneinfo"];
+
+/// Rule used when TZ names a DST zone without saying when DST applies
+/// (the US rules, as glibc assumes)
+const DEFAULT_DST_RULES: &str = "M3.2.0,M11.1.0";
+
+/// The offsets of a time zone through history
+pub struct Zone {
+    /// UTC times at which the offset changes, ascending
+    transitions: Vec<i64>,
+    /// Index into `offsets` from each transition on
+    types: Vec<u8>,
+    /// Offsets from UTC, in seconds east
+    offsets: Vec<i32>,
+    /// Rule for times after the last transition
+    rule: Option<Rule>,
+}
+
+/// The host's time zone, read once; None when it cannot be determined
+pub fn local() -> Option<&'static Zone> {
+    static LOCAL: OnceLock<Option<Zone>> = OnceLock::new();
+    LOCAL.get_or_init(Zone::load).as_ref()
+}
+
+impl Zone {
+    /// The zone selected by TZ, as the C library would choose it
+    fn load() -> Option<Zone> {
+        let Ok(tz) = env::var("TZ") else {
+            return Zone::from_file("/etc/localtime");
+        };
+        let name = tz.strip_prefix(':').unwrap_or(&tz);
+        if name.is_empty() {
+            return Some(Zone::from_rule(Rule::UTC));
+        }
+        if name.starts_with('/') {
+            return Zone::from_file(name);
+        }
+        let from_dirs = || {
+            if name.split('/').any(|c| c == "..") {
+                return None;
+            }
+            let tzdir = env::var("TZDIR").ok();
+            let found = tzdir
+                .as_deref()
+                .into_iter()
+                .chain(ZONEINFO_DIRS)
+                .find_map(|dir| Zone::from_file(&format!("{}/{}", dir, name)));
+            found
+        };
+        from_dirs().or_else(|| Rule::parse(name).map(Zone::from_rule))
+    }
+
+    fn from_file(path: &str) -> Option<Zone> {
+        Zone::parse(&fs::read(path).ok()?)
+    }
+
+    fn from_rule(rule: Rule) -> Zone {
+        Zone {
+            transitions: Vec::new(),
+            types: Vec::new(),
+            offsets: vec![rule.std_offset],
+            rule: Some(rule),
+        }
+    }
+
+    /// Parse a TZif file, version 1 to 4
+    pub fn parse(data: &[u8]) -> Option<Zone> {
+        let (v1, rest) = TzifBlock::parse(data, 4)?;
+        if v1.version == 0 {
+            return v1.zone(rest, None);
+        }
+        // Version 2 and later repeat the data with 64-bit times, followed by
+        // a POSIX rule for times after the last transition
+        let (v2, rest) = TzifBlock::parse(&data[v1.len..], 8)?;
+        let footer = data.get(v1.len + v2.len..)?.strip_prefix(b"\n")?;
+        let end = footer.iter().position(|&b| b == b'\n')?;
+        let rule = match &footer[..end] {
+            b"" => None,
+            spec => Some(Rule::parse(std::str::from_utf8(spec).ok()?)?),
+        };
+        v2.zone(rest, rule)
+    }
+
+    /// Offset from UTC, in seconds, at a UTC time
+    pub fn offset_at(&self, utc: i64) -> i32 {
+        let i = self.transitions.partition_point(|&t| t <= utc);
+        match &self.rule {
+            Some(rule) if i == self.transitions.len() => rule.offset_at(utc),
+            // Before the first transition the first time type applies
+            _ if i == 0 => self.offsets[0],
+            _ => self.offsets[self.types[i - 1] as usize],
+        }
+    }
+
+    /// The UTC time of a local time, given as seconds since 1970-01-01 on
+    /// the local clock. A time repeated when clocks go back is taken as the
+    /// first of the two; one skipped when they go forward is moved forward
+    /// by the change, as mktime() does.
+    pub fn local_to_utc(&self, local: i64) -> i64 {
+        // No zone changes its offset twice within two days
+        let before = self.offset_at(local - 86400);
+        let after = self.offset_at(local + 86400);
+        [before, after]
+            .into_iter()
+            .find(|&offset| self.offset_at(local - offset as i64) == offset)
+            .map_or(local - before as i64, |offset| local - offset as i64)
+    }
+}
+
+/// Header and layout of one data block of a TZif file
+struct TzifBlock {
+    version: u8,
+    /// Bytes per transition time: 4 in the version 1 block, 8 after it
+    time_size: usize,
+    time_count: usize,
+    type_count: usize,
+    /// Length of the header and data
+    len: usize,
+}
+
+impl TzifBlock {
+    /// Read a block header; returns it with the data that follows it
+    fn parse(data: &[u8], time_size: usize) -> Option<(TzifBlock, &[u8])> {
+        if data.len() < 44 || &data[..4] != b"TZif" {
+            return None;
+        }
+        let version = match data[4] {
+            0 => 0,
+            b @ b'2'..=b'9' => b - b'0',
+            _ => return None,
+        };
+        let count = |i: usize| {
+            u32::from_be_bytes(data[20 + i * 4..24 + i * 4].try_into().unwrap())
+                as usize
+        };
+        let (utc_count, std_count, leap_count) = (count(0), count(1), count(2));
+        let (time_count, type_count) = (count(3), count(4));
+        let char_count = count(5);
+        if type_count == 0 {
+            return None;
+        }
+        let len = 44
+            + time_count * (time_size + 1)
+            + type_count * 6
+            + char_count
+            + leap_count * (time_size + 4)
+            + std_count
+            + utc_count;
+        if data.len() < len {
+            return None;
+        }
+        let block = TzifBlock {
+            version,
+            time_size,
+            time_count,
+            type_count,
+            len,
+        };
+        Some((block, &data[44..]))
+    }
+
+    /// The transitions and offsets of a block, from the data after its header
+    fn zone(&self, data: &[u8], rule: Option<Rule>) -> Option<Zone> {
+        let (times, data) = data.split_at(self.time_count * self.time_size);
+        let (types, data) = data.split_at(self.time_count);
+        let transitions = times
+            .chunks_exact(self.time_size)
+            .map(|t| match self.time_size {
+                4 => i32::from_be_bytes(t.try_into().unwrap()) as i64,
+                _ => i64::from_be_bytes(t.try_into().unwrap()),
+            })
+            .collect();
+        if types.iter().any(|&t| t as usize >= self.type_count) {
+            return None;
+        }
+        // Each local time type: 32-bit offset, DST flag, name index
+        let offsets = data[..self.type_count * 6]
+            .chunks_exact(6)
+            .map(|t| i32::from_be_bytes(t[..4].try_into().unwrap()))
+            .collect();
+        Some(Zone {
+            transitions,
+            types: types.to_vec(),
+            offsets,
+            rule,
+        })
+    }
+}
+
+/// A POSIX TZ rule: a standard offset and, optionally, a DST offset with the
+/// local times at which DST starts and ends each year
+#[derive(Debug, PartialEq)]
+struct Rule {
+    /// Seconds east of UTC
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Dst {
+    offset: i32,
+    /// Day, and seconds after midnight (standard time), DST starts
+    start: (RuleDay, i32),
+    /// Day, and seconds after midnight (DST), DST ends
+    end: (RuleDay, i32),
+}
+
+/// A day of the year in a POSIX TZ rule
+#[derive(Debug, PartialEq)]
+enum RuleDay {
+    /// `Jn`: day 1 to 365, never counting February 29th
+    Julian(u16),
+    /// `n`: day 0 to 365, counting February 29th
+    Zero(u16),
+    /// `Mm.w.d`: weekday d (0 is Sunday) of week w (5 is the last) of month m
+    Month(u8, u8, u8),
+}
+
+impl Rule {
+    const UTC: Rule = Rule {
+        std_offset: 0,
+        dst: None,
+    };
+
+    /// Parse a rule such as `EST5EDT,M3.2.0,M11.1.0` or `<+0330>-3:30`
+    fn parse(spec: &str) -> Option<Rule> {
+        let mut p = RuleParser {
+            s: spec.as_bytes(),
+            pos: 0,
+        };
+        p.name()?;
+        // POSIX offsets count hours west of UTC
+        let std_offset = -p.hms(24)?;
+        if p.done() {
+            return Some(Rule {
+                std_offset,
+                dst: None,
+            });
+        }
+        p.name()?;
+        let offset = match p.peek() {
+            Some(b'+' | b'-' | b'0'..=b'9') => -p.hms(24)?,
+            _ => std_offset + 3600,
+        };
+        if p.done() {
+            p = RuleParser {
+                s: DEFAULT_DST_RULES.as_bytes(),
+                pos: 0,
+            };
+        } else {
+            p.eat(b',')?;
+        }
+        let start = p.day_and_time()?;
+        p.eat(b',')?;
+        let end = p.day_and_time()?;
+        if !p.done() {
+            return None;
+        }
+        Some(Rule {
+            std_offset,
+            dst: Some(Dst { offset, start, end }),
+        })
+    }
+
+    fn offset_at(&self, utc: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+        let year = year_of(utc + self.std_offset as i64);
+        let start = dst.start.0.unix_day(year) * 86400 + dst.start.1 as i64
+            - self.std_offset as i64;
+        let end = dst.end.0.unix_day(year) * 86400 + dst.end.1 as i64
+            - dst.offset as i64;
+        // Southern hemisphere rules end DST earlier in the year than they
+        // start it
+        let in_dst = if start < end {
+            utc >= start && utc < end
+        } else {
+            utc < end || utc >= start
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+impl RuleDay {
+    /// Days from 1970-01-01 to this day of `year`
+    fn unix_day(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let leap =
+            days_from_civil(year, 3, 1) - days_from_civil(year, 2, 28) == 2;
+        match *self {
+            RuleDay::Julian(n) => {
+                jan1 + n as i64 - 1 + (leap && n >= 60) as i64
+            }
+            RuleDay::Zero(n) => jan1 + n as i64,
+            RuleDay::Month(month, week, weekday) => {
+                let first = days_from_civil(year, month as i64, 1);
+                let next = match month {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    _ => days_from_civil(year, month as i64 + 1, 1),
+                };
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first
+                    + (weekday as i64 - first_weekday).rem_euclid(7)
+                    + (week as i64 - 1) * 7;
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+/// The year of a day counted in seconds since 1970-01-01
+/// (Howard Hinnant's civil_from_days algorithm)
+fn year_of(secs: i64) -> i64 {
+    let z = secs.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    yoe + era * 400 + (mp >= 10) as i64
+}
+
+struct RuleParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl RuleParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn done(&self) -> bool {
+        self.pos == self.s.len()
+    }
+
+    fn eat(&mut self, c: u8) -> Option<()> {
+        (self.peek() == Some(c)).then(|| self.pos += 1)
+    }
+
+    /// A zone abbreviation: three or more letters, or `<...>`
+    fn name(&mut self) -> Option<()> {
+        let start = self.pos;
+        if self.eat(b'<').is_some() {
+            while self.peek().is_some_and(|c| c != b'>') {
+                self.pos += 1;
+            }
+            self.eat(b'>')?;
+            return (self.pos - start >= 5).then_some(());
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        (self.pos - start >= 3).then_some(())
+    }
+
+    fn number(&mut self, max: u32) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits = std::str::from_utf8(&self.s[start..self.pos]).ok()?;
+        digits.parse().ok().filter(|&n| n <= max)
+    }
+
+    /// `[+|-]hh[:mm[:ss]]` in seconds, hours at most `max_hours`
+    fn hms(&mut self, max_hours: u32) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => -1,
+            _ => 1,
+        };
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.pos += 1;
+        }
+        let mut secs = self.number(max_hours)? * 3600;
+        if self.eat(b':').is_some() {
+            secs += self.number(59)? * 60;
+            if self.eat(b':').is_some() {
+                secs += self.number(59)?;
+            }
+        }
+        Some(sign * secs as i32)
+    }
+
+    /// `date[/time]`, the time defaulting to 02:00
+    fn day_and_time(&mut self) -> Option<(RuleDay, i32)> {
+        let day = if self.eat(b'J').is_some() {
+            RuleDay::Julian(self.number(365).filter(|&n| n >= 1)? as u16)
+        } else if self.eat(b'M').is_some() {
+            let month = self.number(12).filter(|&m| m >= 1)?;
+            self.eat(b'.')?;
+            let week = self.number(5).filter(|&w| w >= 1)?;
+            self.eat(b'.')?;
+            let weekday = self.number(6)?;
+            RuleDay::Month(month as u8, week as u8, weekday as u8)
+        } else {
+            RuleDay::Zero(self.number(365)? as u16)
+        };
+        let time = match self.eat(b'/') {
+            // Version 3 TZif rules allow -167 to 167 hours
+            Some(()) => self.hms(167)?,
+            None => 7200,
+        };
+        Some((day, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since 1970-01-01 of a date and time on some clock
+    fn at(year: i64, month: i64, day: i64, hour: i64, minute: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60
+    }
+
+    fn zone(spec: &str) -> Zone {
+        Zone::from_rule(Rule::parse(spec).unwrap())
+    }
+
+    #[test]
+    fn posix_rules() {
+        for spec in ["EST5EDT,M3.2.0,M11.1.0", "EST5EDT", "EST+5EDT4"] {
+            let z = zone(spec);
+            let utc = |y, m, d, h, min| z.local_to_utc(at(y, m, d, h, min));
+            assert_eq!(utc(2024, 1, 15, 12, 0), at(2024, 1, 15, 17, 0));
+            assert_eq!(utc(2024, 7, 1, 12, 0), at(2024, 7, 1, 16, 0));
+            // 02:30 on March 10th does not exist: it becomes 03:30 EDT
+            assert_eq!(utc(2024, 3, 10, 2, 30), at(2024, 3, 10, 7, 30));
+            // 01:30 on November 3rd happens twice: the first is EDT
+            assert_eq!(utc(2024, 11, 3, 1, 30), at(2024, 11, 3, 5, 30));
+            assert_eq!(utc(2024, 11, 3, 2, 30), at(2024, 11, 3, 7, 30));
+        }
+
+        // Southern hemisphere, DST over the new year, ending at 03:00
+        let z = zone("AEST-10AEDT,M10.1.0,M4.1.0/3");
+        assert_eq!(z.offset_at(at(2024, 1, 15, 0, 0)), 11 * 3600);
+        assert_eq!(z.offset_at(at(2024, 7, 15, 0, 0)), 10 * 3600);
+        assert_eq!(z.offset_at(at(2024, 4, 6, 15, 59)), 11 * 3600);
+        assert_eq!(z.offset_at(at(2024, 4, 6, 16, 0)), 10 * 3600);
+
+        let z = zone("<+0330>-3:30");
+        let utc = z.local_to_utc(at(1994, 6, 15, 12, 0));
+        assert_eq!(utc, at(1994, 6, 15, 8, 30));
+        assert_eq!(zone("UTC0").offset_at(0), 0);
+    }
+
+    #[test]
+    fn rule_days() {
+        // Day 60 is March 1st with or without a leap day; day 59 is not
+        let day = |y, m, d| days_from_civil(y, m, d);
+        assert_eq!(RuleDay::Julian(60).unix_day(2024), day(2024, 3, 1));
+        assert_eq!(RuleDay::Julian(60).unix_day(2023), day(2023, 3, 1));
+        assert_eq!(RuleDay::Zero(59).unix_day(2024), day(2024, 2, 29));
+        // The last Sunday of October 2024 is the 27th, of March the 31st
+        let last = RuleDay::Month(10, 5, 0).unix_day(2024);
+        assert_eq!(last, days_from_civil(2024, 10, 27));
+        let last = RuleDay::Month(3, 5, 0).unix_day(2024);
+        assert_eq!(last, days_from_civil(2024, 3, 31));
+        assert_eq!(year_of(at(2023, 12, 31, 23, 59)), 2023);
+        assert_eq!(year_of(at(2024, 1, 1, 0, 0)), 2024);
+        assert_eq!(year_of(-1), 1969);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for bad in [
+            "",
+            "5",
+            "ES5",
+            "EST",
+            "<ES>5",
+            "EST5EDT,M3.2.0",
+            "EST5EDT,M13.2.0,M11.1.0",
+            "EST5EDT,M3.6.0,M11.1.0",
+            "EST5EDT,J0,J365",
+            "EST5EDT,M3.2.0/168,M11.1.0",
+            "EST25",
+            "EST5x",
+        ] {
+            assert!(Rule::parse(bad).is_none(), "{}", bad);
+        }
+    }
+
+    /// A TZif file with one transition, from +01:00 to +02:00 at `t`
+    fn tzif(version: u8, t: i64, footer: &str) -> Vec<u8> {
+        let block = |time_size: usize| {
+            let mut b = b"TZif".to_vec();
+            b.push(version);
+            b.extend([0; 15]);
+            for count in [0u32, 0, 0, 1, 2, 6] {
+                b.extend(count.to_be_bytes());
+            }
+            match time_size {
+                4 => b.extend((t as i32).to_be_bytes()),
+                _ => b.extend(t.to_be_bytes()),
+            }
+            b.push(1);
+            b.extend(3600i32.to_be_bytes());
+            b.extend([0, 0]);
+            b.extend(7200i32.to_be_bytes());
+            b.extend([1, 3]);
+            b.extend(b"AT\0BT\0");
+            b
+        };
+        let mut data = block(4);
+        if version != 0 {
+            // The 64-bit block is the one used
+            data.extend(block(8));
+            data.extend(format!("\n{}\n", footer).bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn tzif_files() {
+        let t = at(2000, 1, 1, 0, 0);
+        let z = Zone::parse(&tzif(0, t, "")).unwrap();
+        assert_eq!((z.offset_at(t - 1), z.offset_at(t)), (3600, 7200));
+
+        let z = Zone::parse(&tzif(b'2', t, "")).unwrap();
+        assert_eq!((z.offset_at(t - 1), z.offset_at(i64::MAX)), (3600, 7200));
+        // After the last transition the footer rule takes over
+        let z = Zone::parse(&tzif(b'3', t, "BST-2CST,J1/0,J365/24")).unwrap();
+        assert_eq!(z.offset_at(t + 86400 * 30), 3 * 3600);
+
+        let mut bad = tzif(b'2', t, "");
+        bad.truncate(bad.len() - 1);
+        assert!(Zone::parse(&bad).is_none());
+        assert!(Zone::parse(b"TZif").is_none());
+        assert!(Zone::parse(&tzif(b'2', t, "nonsense")).is_none());
+    }
+}